@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{env, path::PathBuf};
 
 use crate::project::Project;
 use crate::Result;
@@ -10,6 +10,10 @@ use super::RootIdentificationResult;
 pub struct Cargo;
 
 impl BuildSystem for Cargo {
+    fn name(&self) -> &'static str {
+        "cargo"
+    }
+
     fn is_project_root(
         &self,
         path: &std::path::Path,
@@ -27,6 +31,14 @@ impl BuildSystem for Cargo {
         Ok(None)
     }
 
+    fn output_dir(&self, project: &Project) -> Option<PathBuf> {
+        let target_dir = match project.env.get("CARGO_TARGET_DIR") {
+            Some(dir) => dir.into(),
+            None => env::var_os("CARGO_TARGET_DIR").unwrap_or("target".into()),
+        };
+        Some(project.project_dir.join(target_dir))
+    }
+
     fn configure_command(&self, _project: &Project) -> Vec<String> {
         vec![]
     }
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{fs, path::PathBuf};
 
 use crate::{project::Project, Result};
 
@@ -8,6 +8,10 @@ use super::{BuildSystem, RootIdentificationResult};
 pub struct CMake;
 
 impl BuildSystem for CMake {
+    fn name(&self) -> &'static str {
+        "cmake"
+    }
+
     fn is_project_root(
         &self,
         path: &std::path::Path,
@@ -26,7 +30,10 @@ impl BuildSystem for CMake {
     }
 
     fn configure_command(&self, project: &Project) -> Vec<String> {
-        let mut command = vec!["cmake".to_string()];
+        let mut command = vec![
+            "cmake".to_string(),
+            "-DCMAKE_EXPORT_COMPILE_COMMANDS=ON".to_string(),
+        ];
         command.extend(project.configure_args.clone());
         command.extend([
             "-G".to_string(),
@@ -48,4 +55,24 @@ impl BuildSystem for CMake {
         command.extend(project.args.clone());
         command
     }
+
+    fn compile_commands(&self, project: &Project) -> Option<PathBuf> {
+        Some(project.build_dir.join("compile_commands.json"))
+    }
+
+    /// Build directories configured before mk enabled the export lack it
+    /// in their cache.
+    fn exports_compile_commands(&self, project: &Project) -> bool {
+        let cache = project.build_dir.join("CMakeCache.txt");
+        let Ok(cache) = fs::read_to_string(cache) else {
+            return true;
+        };
+        cache.lines().any(|line| {
+            line.strip_prefix("CMAKE_EXPORT_COMPILE_COMMANDS:BOOL=")
+                .is_some_and(|x| {
+                    ["ON", "TRUE", "YES", "Y", "1"]
+                        .contains(&x.to_uppercase().as_str())
+                })
+        })
+    }
 }
@@ -1,5 +1,6 @@
 use std::path::PathBuf;
 
+use crate::condition::find_in_path;
 use crate::project::Project;
 use crate::Result;
 
@@ -10,6 +11,10 @@ use super::RootIdentificationResult;
 pub struct Make;
 
 impl BuildSystem for Make {
+    fn name(&self) -> &'static str {
+        "make"
+    }
+
     fn is_project_root(
         &self,
         path: &std::path::Path,
@@ -31,16 +36,35 @@ impl BuildSystem for Make {
         Ok(None)
     }
 
+    fn output_dir(&self, _project: &Project) -> Option<PathBuf> {
+        None
+    }
+
     fn configure_command(&self, _project: &Project) -> Vec<String> {
         vec![]
     }
 
     fn build_command(&self, project: &Project) -> Vec<String> {
-        let mut command = vec![
+        let mut command = vec![];
+        // bear records the compiler invocations of the build. It only runs
+        // while there is no database, as it slows the build down.
+        let database = project.project_dir.join("compile_commands.json");
+        if !project.container
+            && !database.exists()
+            && find_in_path("bear").is_some()
+        {
+            command.extend([
+                "bear".to_string(),
+                "--output".to_string(),
+                database.to_string_lossy().to_string(),
+                "--".to_string(),
+            ]);
+        }
+        command.extend([
             "make".to_string(),
             "-C".to_string(),
             project.project_dir.to_string_lossy().to_string(),
-        ];
+        ]);
         command.extend(project.args.clone());
         command
     }
@@ -8,6 +8,10 @@ use super::{BuildSystem, RootIdentificationResult};
 pub struct Meson;
 
 impl BuildSystem for Meson {
+    fn name(&self) -> &'static str {
+        "meson"
+    }
+
     fn is_project_root(
         &self,
         path: &std::path::Path,
@@ -64,4 +68,21 @@ impl BuildSystem for Meson {
         command.extend(project.args.clone());
         command
     }
+
+    fn compile_commands(&self, project: &Project) -> Option<PathBuf> {
+        Some(project.build_dir.join("compile_commands.json"))
+    }
+
+    fn compile_commands_command(
+        &self,
+        project: &Project,
+    ) -> Option<Vec<String>> {
+        Some(vec![
+            "ninja".to_string(),
+            "-C".to_string(),
+            project.build_dir.to_string_lossy().to_string(),
+            "-t".to_string(),
+            "compdb".to_string(),
+        ])
+    }
 }
@@ -30,8 +30,37 @@ pub fn build_system_from_str(name: &str) -> Option<&'static dyn BuildSystem> {
 }
 
 pub trait BuildSystem: Debug + Sync {
+    fn name(&self) -> &'static str;
     fn is_project_root(&self, path: &Path) -> Result<RootIdentificationResult>;
     fn configure_marker(&self, project: &Project) -> Result<Option<PathBuf>>;
     fn configure_command(&self, project: &Project) -> Vec<String>;
     fn build_command(&self, project: &Project) -> Vec<String>;
+
+    /// The directory the build writes its output to, where mk keeps its own
+    /// files for the project. `None` if the output ends up in the source
+    /// tree.
+    fn output_dir(&self, project: &Project) -> Option<PathBuf> {
+        Some(project.build_dir.clone())
+    }
+
+    /// The compilation database the build system writes to the build
+    /// directory, if it writes one.
+    fn compile_commands(&self, _project: &Project) -> Option<PathBuf> {
+        None
+    }
+
+    /// Whether the configured build directory writes the compilation
+    /// database. Only build systems that have to be asked for it say no.
+    fn exports_compile_commands(&self, _project: &Project) -> bool {
+        true
+    }
+
+    /// A command that prints the compilation database, for the case that
+    /// the configure step did not write it.
+    fn compile_commands_command(
+        &self,
+        _project: &Project,
+    ) -> Option<Vec<String>> {
+        None
+    }
 }
@@ -0,0 +1,134 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use crate::diagnostics::{resolve_path, Diagnostic, Severity};
+
+/// The continuous integration service mk runs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    GitHubActions,
+    /// Any service that sets `CI`, which gets plain output.
+    Other,
+}
+
+impl Service {
+    pub fn detect() -> Option<Self> {
+        let is_set = |name| {
+            env::var(name)
+                .is_ok_and(|x| !matches!(x.as_str(), "" | "0" | "false"))
+        };
+        if env::var("GITHUB_ACTIONS").is_ok_and(|x| x == "true") {
+            Some(Self::GitHubActions)
+        } else if is_set("CI") {
+            Some(Self::Other)
+        } else {
+            None
+        }
+    }
+}
+
+/// Escapes the message of a workflow command.
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escapes a property of a workflow command.
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Makes `file`, relative to `work_dir`, relative to the checkout, which is
+/// what GitHub expects in annotations.
+fn workspace_path(file: &Path, work_dir: &Path) -> PathBuf {
+    match env::var_os("GITHUB_WORKSPACE") {
+        Some(workspace) => {
+            resolve_path(&work_dir.join(file), &[], Path::new(&workspace))
+        }
+        None => file.to_path_buf(),
+    }
+}
+
+/// The workflow command that shows `diagnostic` as an annotation.
+pub fn annotation(diagnostic: &Diagnostic, work_dir: &Path) -> String {
+    let command = match diagnostic.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "notice",
+    };
+    let file = workspace_path(&diagnostic.file, work_dir);
+    let mut properties = format!(
+        "file={},line={}",
+        escape_property(&file.to_string_lossy()),
+        diagnostic.line
+    );
+    if let Some(column) = diagnostic.column {
+        properties.push_str(&format!(",col={}", column));
+    }
+    format!(
+        "::{} {}::{}",
+        command,
+        properties,
+        escape_data(&diagnostic.message)
+    )
+}
+
+/// Starts a collapsible group of log lines.
+pub fn group(title: &str) {
+    println!("::group::{}", escape_data(title));
+}
+
+pub fn end_group() {
+    println!("::endgroup::");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(file: &str, column: Option<usize>) -> Diagnostic {
+        Diagnostic {
+            file: PathBuf::from(file),
+            line: 3,
+            column,
+            severity: Severity::Error,
+            message: "100% wrong\r\nreally".into(),
+        }
+    }
+
+    #[test]
+    fn escapes_messages() {
+        assert_eq!(escape_data("50% done\r\nnext"), "50%25 done%0D%0Anext");
+        assert_eq!(escape_data("a: b, c"), "a: b, c");
+    }
+
+    #[test]
+    fn escapes_properties() {
+        assert_eq!(escape_property("C:\\a,b%\n"), "C%3A\\a%2Cb%25%0A");
+    }
+
+    // The only test that touches `GITHUB_WORKSPACE`, so it does not race
+    // with others.
+    #[test]
+    fn annotations() {
+        let work_dir = Path::new("/work/app");
+        env::remove_var("GITHUB_WORKSPACE");
+        assert_eq!(
+            annotation(&diagnostic("src/a:b,c.c", Some(7)), work_dir),
+            "::error file=src/a%3Ab%2Cc.c,line=3,col=7::\
+             100%25 wrong%0D%0Areally"
+        );
+
+        env::set_var("GITHUB_WORKSPACE", "/work");
+        let mut note = diagnostic("src/a.c", None);
+        note.severity = Severity::Note;
+        assert_eq!(
+            annotation(&note, work_dir),
+            "::notice file=app/src/a.c,line=3::100%25 wrong%0D%0Areally"
+        );
+        env::remove_var("GITHUB_WORKSPACE");
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    env,
+    ffi::CStr,
+    path::{Path, PathBuf},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::mk_info::StringOrArray;
+
+/// Host properties a `when` block can check. All given properties have to
+/// match. `os`, `arch` and `hostname` match if the host equals any of the
+/// listed values, `tool` and `env` require all listed tools to be on the
+/// `PATH` or all listed variables to be set.
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, Clone)]
+#[schemars(deny_unknown_fields)]
+pub struct Condition {
+    pub os: Option<StringOrArray>,
+    pub arch: Option<StringOrArray>,
+    pub hostname: Option<StringOrArray>,
+    pub tool: Option<StringOrArray>,
+    pub env: Option<StringOrArray>,
+}
+
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return path.is_file().then_some(path);
+    }
+    let path_var = env::var("PATH").ok()?;
+    path_var
+        .split(':')
+        .map(|dir| Path::new(dir).join(name))
+        .find(|path| path.is_file())
+}
+
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    let result = unsafe {
+        libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len())
+    };
+    if result != 0 {
+        return None;
+    }
+    let name = CStr::from_bytes_until_nul(&buf).ok()?;
+    Some(name.to_string_lossy().to_string())
+}
+
+fn values(field: &Option<StringOrArray>) -> Option<Vec<String>> {
+    field.clone().map(Vec::from)
+}
+
+impl Condition {
+    pub fn matches(&self) -> bool {
+        let any_of = |field: &Option<StringOrArray>, value: Option<&str>| {
+            values(field).is_none_or(|x| {
+                value.is_some_and(|value| x.iter().any(|x| x == value))
+            })
+        };
+        let all_of = |field: &Option<StringOrArray>,
+                      check: fn(&str) -> bool| {
+            values(field).is_none_or(|x| x.iter().all(|x| check(x)))
+        };
+
+        any_of(&self.os, Some(env::consts::OS))
+            && any_of(&self.arch, Some(env::consts::ARCH))
+            && any_of(&self.hostname, hostname().as_deref())
+            && all_of(&self.tool, |x| find_in_path(x).is_some())
+            && all_of(&self.env, |x| env::var_os(x).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(yaml: &str) -> bool {
+        serde_yaml::from_str::<Condition>(yaml).unwrap().matches()
+    }
+
+    #[test]
+    fn empty_condition_matches() {
+        assert!(matches("{}"));
+    }
+
+    #[test]
+    fn host_matches_any_value() {
+        let os = env::consts::OS;
+        assert!(matches(&format!("os: {}", os)));
+        assert!(matches(&format!("os: [mk-test-os, {}]", os)));
+        assert!(!matches("os: mk-test-os"));
+        assert!(matches(&format!("arch: [{}]", env::consts::ARCH)));
+        assert!(!matches("arch: [mk-test-arch]"));
+        assert!(!matches("hostname: mk-test-host.invalid"));
+    }
+
+    #[test]
+    fn tools_and_env_require_all_values() {
+        assert!(matches("tool: sh"));
+        assert!(!matches("tool: [sh, mk-test-missing-tool]"));
+        assert!(matches("env: PATH"));
+        assert!(!matches("env: [PATH, MK_TEST_UNSET_VARIABLE]"));
+    }
+
+    #[test]
+    fn all_checks_have_to_match() {
+        let os = env::consts::OS;
+        assert!(matches(&format!("{{os: {}, tool: sh}}", os)));
+        assert!(!matches(&format!("{{os: {}, tool: mk-test-missing}}", os)));
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+};
+
+use crate::{project::Project, Error, Opts, Result};
+
+/// Projects in build order, dependencies before their dependents.
+pub struct Graph {
+    pub projects: Vec<Project>,
+    /// The indices of the direct dependencies of each project.
+    pub dependencies: Vec<Vec<usize>>,
+}
+
+struct Resolver<'a> {
+    opts: &'a Opts,
+    graph: Graph,
+    index: HashMap<PathBuf, usize>,
+    /// The pkg-config directories each project and its dependencies provide.
+    pkg_config_dirs: Vec<Vec<PathBuf>>,
+    stack: Vec<PathBuf>,
+}
+
+/// Loads the dependency at `path` with the options of the dependent. Build
+/// system arguments, modes and the build directory only apply to the
+/// projects that are built directly.
+fn load(path: &Path, opts: &Opts) -> Result<Project> {
+    let opts = Opts {
+        cwd: path.to_path_buf(),
+        args: vec![],
+        modes: vec![],
+        build_dir: None,
+        ..opts.clone()
+    };
+    Project::from_opts(&opts)
+        .map_err(|e| Error::Dependency(path.into(), Box::new(e)))
+}
+
+/// The directory with the uninstalled pkg-config files of a meson build.
+fn pkg_config_dir(project: &Project) -> Option<PathBuf> {
+    (project.build_system.name() == "meson")
+        .then(|| project.build_dir.join("meson-uninstalled"))
+}
+
+impl Resolver<'_> {
+    fn add(&mut self, mut project: Project) -> Result<usize> {
+        let dir = project.project_dir.clone();
+        if let Some(index) = self.index.get(&dir) {
+            return Ok(*index);
+        }
+        if let Some(pos) = self.stack.iter().position(|x| *x == dir) {
+            let cycle: Vec<String> = self.stack[pos..]
+                .iter()
+                .chain([&dir])
+                .map(|x| x.display().to_string())
+                .collect();
+            return Err(Error::DependencyCycle(cycle.join(" -> ")));
+        }
+
+        self.stack.push(dir.clone());
+        let mut dependencies = vec![];
+        for path in project.depends_on.clone() {
+            let dependency = load(&path, self.opts)?;
+            let index = self.add(dependency)?;
+            if !dependencies.contains(&index) {
+                dependencies.push(index);
+            }
+        }
+        self.stack.pop();
+
+        let mut pkg_config_dirs: Vec<PathBuf> = vec![];
+        for index in &dependencies {
+            for dir in &self.pkg_config_dirs[*index] {
+                if !pkg_config_dirs.contains(dir) {
+                    pkg_config_dirs.push(dir.clone());
+                }
+            }
+        }
+        if !pkg_config_dirs.is_empty() {
+            export_pkg_config_path(&mut project, &pkg_config_dirs);
+        }
+        pkg_config_dirs.extend(pkg_config_dir(&project));
+
+        let index = self.graph.projects.len();
+        self.graph.projects.push(project);
+        self.graph.dependencies.push(dependencies);
+        self.pkg_config_dirs.push(pkg_config_dirs);
+        self.index.insert(dir, index);
+        Ok(index)
+    }
+}
+
+/// Prepends the pkg-config directories of the dependencies to the
+/// `PKG_CONFIG_PATH` of `project`.
+fn export_pkg_config_path(project: &mut Project, dirs: &[PathBuf]) {
+    let existing = project
+        .env
+        .get("PKG_CONFIG_PATH")
+        .cloned()
+        .or_else(|| env::var("PKG_CONFIG_PATH").ok());
+    let mut paths: Vec<String> =
+        dirs.iter().map(|x| x.display().to_string()).collect();
+    paths.extend(existing.filter(|x| !x.is_empty()));
+    project
+        .env
+        .insert("PKG_CONFIG_PATH".into(), paths.join(":"));
+}
+
+/// Resolves the `depends_on` entries of `projects` recursively and sorts
+/// all projects topologically. Projects that are reached more than once are
+/// only built once.
+pub fn resolve(projects: Vec<Project>, opts: &Opts) -> Result<Graph> {
+    let mut resolver = Resolver {
+        opts,
+        graph: Graph {
+            projects: vec![],
+            dependencies: vec![],
+        },
+        index: HashMap::new(),
+        pkg_config_dirs: vec![],
+        stack: vec![],
+    };
+    for project in projects {
+        resolver.add(project)?;
+    }
+    Ok(resolver.graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "mk-dependencies-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    /// Creates a Make project `name` in `root` that depends on `depends_on`.
+    fn project(root: &Path, name: &str, depends_on: &[&str]) {
+        let dir = root.join(name);
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join("Makefile"), "").unwrap();
+        let depends_on: Vec<_> =
+            depends_on.iter().map(|x| format!("../{}", x)).collect();
+        let mk_yaml = format!(
+            "depends_on: [{}]\nmode: {{release: {{}}}}\n",
+            depends_on.join(", ")
+        );
+        fs::write(dir.join(".Mk.yaml"), mk_yaml).unwrap();
+    }
+
+    fn opts(dir: PathBuf) -> Opts {
+        Opts {
+            cwd: dir,
+            ..Default::default()
+        }
+    }
+
+    fn resolve_dir(dir: PathBuf, opts: Opts) -> Result<Graph> {
+        let opts = Opts { cwd: dir, ..opts };
+        resolve(vec![Project::from_opts(&opts)?], &opts)
+    }
+
+    fn names(graph: &Graph) -> Vec<String> {
+        graph
+            .projects
+            .iter()
+            .map(|x| x.project_dir.file_name().unwrap())
+            .map(|x| x.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn dependencies_come_first_and_once() {
+        let root = temp_dir("order");
+        project(&root, "app", &["a", "b"]);
+        project(&root, "a", &["c"]);
+        project(&root, "b", &["c", "c"]);
+        project(&root, "c", &[]);
+
+        let graph = resolve_dir(root.join("app"), opts(root.clone())).unwrap();
+        assert_eq!(names(&graph), ["c", "a", "b", "app"]);
+        assert_eq!(graph.dependencies, [vec![], vec![0], vec![0], vec![1, 2]]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn dependency_cycle() {
+        let root = temp_dir("cycle");
+        project(&root, "a", &["b"]);
+        project(&root, "b", &["a"]);
+
+        let error = resolve_dir(root.join("a"), opts(root.clone()))
+            .err()
+            .unwrap();
+        let message = format!(
+            "{} -> {} -> {}",
+            root.join("a").display(),
+            root.join("b").display(),
+            root.join("a").display()
+        );
+        assert!(
+            matches!(&error, Error::DependencyCycle(x) if *x == message),
+            "{:?}",
+            error
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn dependencies_ignore_invocation_options() {
+        let root = temp_dir("opts");
+        project(&root, "app", &["lib"]);
+        project(&root, "lib", &[]);
+
+        let opts = Opts {
+            args: vec!["install".into()],
+            build_dir: Some(root.join("out")),
+            modes: vec!["release".into()],
+            ..opts(root.clone())
+        };
+        let graph = resolve_dir(root.join("app"), opts).unwrap();
+        let [lib, app] = &graph.projects[..] else {
+            panic!("expected two projects");
+        };
+        assert_eq!(app.build_dir, root.join("out"));
+        assert_eq!(app.modes, ["release"]);
+        assert_eq!(lib.build_dir, root.join("lib/build"));
+        assert_eq!(lib.modes, ["default"]);
+        assert!(lib.args.is_empty(), "{:?}", lib.args);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
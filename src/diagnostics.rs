@@ -0,0 +1,498 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "error" | "fatal error" => Some(Self::Error),
+            "warning" => Some(Self::Warning),
+            "note" => Some(Self::Note),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Note => "note",
+        })
+    }
+}
+
+/// A compiler or build system message with a location.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    /// Formats the diagnostic like GCC, which vim's default `errorformat`
+    /// understands.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:", self.file.display(), self.line)?;
+        if let Some(column) = self.column {
+            write!(f, "{}:", column)?;
+        }
+        write!(f, " {}: {}", self.severity, self.message)
+    }
+}
+
+/// Removes the ANSI escape sequences compilers use for colors.
+pub fn strip_ansi(line: &str) -> Cow<'_, str> {
+    if !line.contains('\x1b') {
+        return Cow::Borrowed(line);
+    }
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            result.push(c);
+        } else if chars.next() == Some('[') {
+            // Skip the parameters up to the final byte of the sequence.
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    Cow::Owned(result)
+}
+
+/// Splits the leading digits off `s`.
+fn split_number(s: &str) -> Option<(usize, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    Some((s[..end].parse().ok()?, &s[end..]))
+}
+
+/// Splits `file:line:column: rest` or `file:line: rest`.
+fn split_location(line: &str) -> Option<(&str, usize, Option<usize>, &str)> {
+    for (pos, _) in line.match_indices(':').filter(|(pos, _)| *pos > 0) {
+        let Some((line_number, rest)) = split_number(&line[pos + 1..]) else {
+            continue;
+        };
+        let Some(rest) = rest.strip_prefix(':') else {
+            continue;
+        };
+        let (column, rest) = match split_number(rest) {
+            Some((column, tail)) if tail.starts_with(':') => {
+                (Some(column), &tail[1..])
+            }
+            _ => (None, rest),
+        };
+        return Some((&line[..pos], line_number, column, rest.trim_start()));
+    }
+    None
+}
+
+/// Splits `severity: message` or `severity[code]: message`.
+fn split_severity(text: &str) -> Option<(Severity, &str)> {
+    let (name, message) = text.split_once(": ")?;
+    let name = name.split_once('[').map_or(name, |(name, _)| name);
+    Some((Severity::parse(name)?, message.trim()))
+}
+
+/// Splits `file:line:column` as rustc prints it after `-->`.
+fn split_rustc_location(
+    location: &str,
+) -> Option<(&str, usize, Option<usize>)> {
+    let (rest, column) = location.rsplit_once(':')?;
+    let (file, line) = rest.rsplit_once(':')?;
+    Some((file, line.parse().ok()?, column.parse().ok()))
+}
+
+#[derive(Default)]
+enum Pending {
+    #[default]
+    None,
+    /// A rustc message, whose location follows on the next line.
+    Rustc(Severity, String),
+    /// A CMake message, whose text follows on the next lines.
+    CMake(Severity, PathBuf, usize),
+}
+
+/// Finds diagnostics in the output of a build, line by line. It recognizes
+/// the formats of GCC and Clang, rustc, meson and CMake.
+#[derive(Default)]
+pub struct Parser {
+    pending: Pending,
+    /// The directories make reports entering.
+    dirs: Vec<PathBuf>,
+}
+
+impl Parser {
+    fn path(&self, file: &str) -> PathBuf {
+        match self.dirs.last() {
+            Some(dir) => dir.join(file),
+            None => PathBuf::from(file),
+        }
+    }
+
+    pub fn parse_line(&mut self, line: &str) -> Option<Diagnostic> {
+        let line = strip_ansi(line);
+        let line = line.trim_end();
+
+        match std::mem::replace(&mut self.pending, Pending::None) {
+            Pending::Rustc(severity, message) => {
+                // `  --> src/main.rs:2:5`
+                let location = line.trim_start().strip_prefix("--> ");
+                if let Some((file, line, column)) =
+                    location.and_then(split_rustc_location)
+                {
+                    return Some(Diagnostic {
+                        file: self.path(file),
+                        line,
+                        column,
+                        severity,
+                        message,
+                    });
+                }
+            }
+            Pending::CMake(severity, file, line_number) => {
+                if line.is_empty() {
+                    self.pending = Pending::CMake(severity, file, line_number);
+                    return None;
+                }
+                return Some(Diagnostic {
+                    file,
+                    line: line_number,
+                    column: None,
+                    severity,
+                    message: line.trim().to_string(),
+                });
+            }
+            Pending::None => {}
+        }
+
+        if let Some(dir) = make_directory(line, "Entering") {
+            self.dirs.push(dir.into());
+            return None;
+        }
+        if make_directory(line, "Leaving").is_some() {
+            self.dirs.pop();
+            return None;
+        }
+
+        // `CMake Error at CMakeLists.txt:3 (add_executable):`
+        if let Some(rest) = line.strip_prefix("CMake ") {
+            let (kind, location) = rest.split_once(" at ")?;
+            let severity = Severity::parse(kind.split(' ').next()?)?;
+            let location = match location.rsplit_once(" (") {
+                Some((location, _)) => location,
+                None => location.trim_end_matches(':'),
+            };
+            let (file, line_number) = location.rsplit_once(':')?;
+            let file = self.path(file);
+            self.pending =
+                Pending::CMake(severity, file, line_number.parse().ok()?);
+            return None;
+        }
+
+        // `error[E0425]: cannot find value `x` in this scope`
+        if let Some((severity, message)) = split_severity(line) {
+            self.pending = Pending::Rustc(severity, message.to_string());
+            return None;
+        }
+
+        // `src/main.c:3:5: error: ...` or `meson.build:3:0: ERROR: ...`
+        let (file, line_number, column, rest) = split_location(line)?;
+        let (severity, message) = split_severity(rest)?;
+        Some(Diagnostic {
+            file: self.path(file),
+            line: line_number,
+            column,
+            severity,
+            message: message.to_string(),
+        })
+    }
+}
+
+/// The directory of a `make: Entering directory '...'` line.
+fn make_directory<'a>(line: &'a str, action: &str) -> Option<&'a str> {
+    let (program, rest) = line.split_once(": ")?;
+    if !program.starts_with("make") {
+        return None;
+    }
+    let dir = rest.strip_prefix(action)?.strip_prefix(" directory ")?;
+    dir.get(1..dir.len().checked_sub(1)?)
+}
+
+/// Removes `.` and `..` components without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match result.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    result.pop();
+                }
+                // `/..` is `/`.
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => result.push(".."),
+            },
+            component => result.push(component),
+        }
+    }
+    result
+}
+
+/// Makes `path` relative to `base` if both are absolute and share more than
+/// the filesystem root.
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path_components: Vec<_> = path.components().collect();
+    let base_components: Vec<_> = base.components().collect();
+    let common = path_components
+        .iter()
+        .zip(&base_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common <= 1 {
+        return path.to_path_buf();
+    }
+    let mut result: PathBuf = base_components[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .collect();
+    result.extend(&path_components[common..]);
+    result
+}
+
+/// Resolves a path of a diagnostic against the first of `dirs` in which it
+/// exists and makes it relative to `work_dir`.
+pub fn resolve_path(file: &Path, dirs: &[&Path], work_dir: &Path) -> PathBuf {
+    let path = if file.is_absolute() {
+        normalize(file)
+    } else {
+        let candidates: Vec<PathBuf> =
+            dirs.iter().map(|dir| normalize(&dir.join(file))).collect();
+        candidates
+            .iter()
+            .find(|x| x.exists())
+            .or(candidates.first())
+            .cloned()
+            .unwrap_or_else(|| file.to_path_buf())
+    };
+    relative_to(&path, work_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(output: &str) -> Vec<Diagnostic> {
+        let mut parser = Parser::default();
+        output
+            .lines()
+            .filter_map(|x| parser.parse_line(x))
+            .collect()
+    }
+
+    fn format(output: &str) -> Vec<String> {
+        parse(output).iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn gcc() {
+        let output = "\
+src/main.c: In function 'main':
+src/main.c:3:5: error: 'x' undeclared (first use in this function)
+    3 |     x = 1;
+      |     ^
+src/main.c:3:5: note: each undeclared identifier is reported only once
+cc1: warning: command-line option '-Wfoo' is not valid
+make: *** [Makefile:2: all] Error 1
+";
+        assert_eq!(
+            format(output),
+            [
+                "src/main.c:3:5: error: 'x' undeclared (first use in this \
+                 function)",
+                "src/main.c:3:5: note: each undeclared identifier is reported \
+                 only once",
+            ]
+        );
+    }
+
+    #[test]
+    fn clang_with_colors() {
+        let output = "\x1b[1mx.c:1:13: \x1b[0m\x1b[0;1;35mwarning: \x1b[0m\
+                      \x1b[1munused variable 'y' [-Wunused-variable]\x1b[0m\n\
+                      In file included from a.h:1:\n\
+                      fatal error: too many errors emitted, stopping now\n";
+        assert_eq!(
+            format(output),
+            ["x.c:1:13: warning: unused variable 'y' [-Wunused-variable]"]
+        );
+    }
+
+    #[test]
+    fn gcc_without_column() {
+        let diagnostics = parse("lib.h:7: warning: \"FOO\" redefined\n");
+        assert_eq!(diagnostics[0].file, Path::new("lib.h"));
+        assert_eq!(diagnostics[0].line, 7);
+        assert_eq!(diagnostics[0].column, None);
+        assert_eq!(diagnostics[0].message, "\"FOO\" redefined");
+    }
+
+    #[test]
+    fn rustc() {
+        let output = "\
+   Compiling app v0.1.0 (/src/app)
+error[E0425]: cannot find value `x` in this scope
+ --> src/main.rs:2:13
+  |
+2 |     let y = x;
+  |             ^ not found in this scope
+
+warning: unused variable: `y`
+  --> src/lib.rs:10:9
+   |
+warning: `app` (bin \"app\") generated 1 warning
+error: could not compile `app` (bin \"app\") due to 1 previous error
+";
+        assert_eq!(
+            format(output),
+            [
+                "src/main.rs:2:13: error: cannot find value `x` in this scope",
+                "src/lib.rs:10:9: warning: unused variable: `y`",
+            ]
+        );
+    }
+
+    #[test]
+    fn meson() {
+        let output = "\
+The Meson build system
+meson.build:3:0: ERROR: Unknown variable \"foo\".
+";
+        assert_eq!(
+            format(output),
+            ["meson.build:3:0: error: Unknown variable \"foo\"."]
+        );
+    }
+
+    #[test]
+    fn cmake() {
+        let output = "\
+CMake Error at CMakeLists.txt:3 (add_executable):
+  Cannot find source file:
+
+    missing.c
+
+CMake Warning (dev) at cmake/deps.cmake:12 (message):
+
+  Deprecated option
+-- Configuring incomplete, errors occurred!
+";
+        assert_eq!(
+            format(output),
+            [
+                "CMakeLists.txt:3: error: Cannot find source file:",
+                "cmake/deps.cmake:12: warning: Deprecated option",
+            ]
+        );
+    }
+
+    #[test]
+    fn make_directories() {
+        let output = "\
+make: Entering directory '/src/app'
+make[1]: Entering directory '/src/app/lib'
+foo.c:1:1: error: nested
+make[1]: Leaving directory '/src/app/lib'
+bar.c:2:2: warning: outer
+make: Leaving directory '/src/app'
+baz.c:3:3: note: outside
+";
+        let files: Vec<_> = parse(output).into_iter().map(|x| x.file).collect();
+        assert_eq!(
+            files,
+            [
+                PathBuf::from("/src/app/lib/foo.c"),
+                PathBuf::from("/src/app/bar.c"),
+                PathBuf::from("baz.c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rustc_location_in_make_directory() {
+        let output = "\
+make: Entering directory '/src/app'
+warning: unused import
+ --> src/lib.rs:1:5
+";
+        assert_eq!(parse(output)[0].file, Path::new("/src/app/src/lib.rs"));
+    }
+
+    #[test]
+    fn strips_ansi_sequences() {
+        assert_eq!(strip_ansi("plain"), "plain");
+        assert_eq!(strip_ansi("\x1b[01;31m\x1b[Kerror\x1b[m\x1b[K:"), "error:");
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize(Path::new("/../a")), Path::new("/a"));
+        assert_eq!(normalize(Path::new("./a/.")), Path::new("a"));
+        assert_eq!(normalize(Path::new("a/../../b")), Path::new("../b"));
+        assert_eq!(normalize(Path::new("../../a")), Path::new("../../a"));
+    }
+
+    #[test]
+    fn relative_paths() {
+        let base = Path::new("/home/user/app");
+        let relative = |path| relative_to(Path::new(path), base);
+        assert_eq!(relative("/home/user/app/src/a.c"), Path::new("src/a.c"));
+        assert_eq!(relative("/home/user/lib/b.c"), Path::new("../lib/b.c"));
+        assert_eq!(relative("/home/user/app"), Path::new(""));
+        // Only the root in common.
+        assert_eq!(relative("/usr/include/c.h"), Path::new("/usr/include/c.h"));
+        // Relative paths are left alone.
+        assert_eq!(relative("src/a.c"), Path::new("src/a.c"));
+    }
+
+    #[test]
+    fn resolves_paths_against_existing_candidates() {
+        let root = std::env::temp_dir()
+            .join(format!("mk-diagnostics-test-{}", std::process::id()));
+        let build = root.join("build");
+        let src = root.join("src");
+        std::fs::create_dir_all(&build).unwrap();
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(src.join("a.c"), "").unwrap();
+
+        let dirs = [&*build, &*src];
+        let resolve = |file| resolve_path(Path::new(file), &dirs, &root);
+        assert_eq!(resolve("a.c"), Path::new("src/a.c"));
+        // The first candidate if the file is nowhere.
+        assert_eq!(resolve("gen.c"), Path::new("build/gen.c"));
+        assert_eq!(resolve("../src/./a.c"), Path::new("src/a.c"));
+        let absolute = src.join("../src/a.c");
+        assert_eq!(resolve(absolute.to_str().unwrap()), Path::new("src/a.c"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,21 +1,358 @@
 use crate::{Error, Result};
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
-use serde::Deserialize;
-
-#[derive(Deserialize, Debug)]
+/// A parsed `.editorconfig` file.
+#[derive(Debug, Default)]
 pub struct EditorConfig {
-    #[serde(default)]
     pub root: bool,
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug)]
+pub struct Section {
+    pub glob: String,
+    /// Properties with lowercase keys.
+    pub properties: HashMap<String, String>,
+    pattern: Vec<Token>,
 }
 
 impl EditorConfig {
     pub fn from_file(path: &Path) -> Result<Self> {
-        let file =
-            std::fs::File::open(path).map_err(|e| Error::Io(path.into(), e))?;
-        let reader = std::io::BufReader::new(file);
-        let config = serde_ini::from_read(reader)
-            .map_err(|e| Error::SerdeIni(path.into(), e))?;
-        Ok(config)
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| Error::Io(path.into(), e))?;
+        Ok(Self::parse(&content))
+    }
+
+    /// Parses the content of an `.editorconfig`. Like the reference
+    /// implementation, lines that cannot be parsed are ignored.
+    pub fn parse(content: &str) -> Self {
+        let mut config = Self::default();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(glob) =
+                line.strip_prefix('[').and_then(|x| x.strip_suffix(']'))
+            {
+                config.sections.push(Section::new(glob));
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = key.trim().to_lowercase();
+            let value = value.trim().to_string();
+            match config.sections.last_mut() {
+                Some(section) => {
+                    section.properties.insert(key, value);
+                }
+                None if key == "root" => {
+                    config.root = value.eq_ignore_ascii_case("true");
+                }
+                None => {}
+            }
+        }
+        config
+    }
+
+    /// The properties for `path`, relative to the directory of the
+    /// `.editorconfig`. Later sections take precedence.
+    pub fn properties(&self, path: &Path) -> HashMap<String, String> {
+        let path = path.to_string_lossy();
+        self.sections
+            .iter()
+            .filter(|x| x.matches(&path))
+            .flat_map(|x| x.properties.clone())
+            .collect()
+    }
+}
+
+impl Section {
+    fn new(glob: &str) -> Self {
+        // Globs without a slash match in any directory, others are relative
+        // to the directory of the .editorconfig.
+        let pattern = if glob.contains('/') {
+            glob.strip_prefix('/').unwrap_or(glob).to_string()
+        } else {
+            format!("**/{}", glob)
+        };
+        Self {
+            glob: glob.to_string(),
+            properties: HashMap::new(),
+            pattern: parse_glob(&pattern),
+        }
+    }
+
+    /// Checks if the section applies to `path`, relative to the directory
+    /// of the `.editorconfig`.
+    pub fn matches(&self, path: &str) -> bool {
+        let path = path.strip_prefix('/').unwrap_or(path);
+        let chars: Vec<char> = path.chars().collect();
+        matches(&self.pattern, &chars)
+            || self.pattern.first() == Some(&Token::AnyDeep)
+                && self.pattern.get(1) == Some(&Token::Literal('/'))
+                && matches(&self.pattern[2..], &chars)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    /// `?`
+    One,
+    /// `*`
+    Any,
+    /// `**`
+    AnyDeep,
+    /// `[a-z]` or `[!a-z]`
+    Class(bool, Vec<(char, char)>),
+    /// `{a,b}`
+    Alternatives(Vec<Vec<Token>>),
+    /// `{1..10}`
+    Range(i64, i64),
+}
+
+fn parse_glob(glob: &str) -> Vec<Token> {
+    let chars: Vec<char> = glob.chars().collect();
+    parse_tokens(&chars)
+}
+
+fn parse_tokens(chars: &[char]) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                tokens.push(Token::Literal(chars[i + 1]));
+                i += 2;
+                continue;
+            }
+            '?' => tokens.push(Token::One),
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                tokens.push(Token::AnyDeep);
+                i += 1;
+            }
+            '*' => tokens.push(Token::Any),
+            '[' => match parse_class(&chars[i + 1..]) {
+                Some((token, len)) => {
+                    tokens.push(token);
+                    i += len + 1;
+                    continue;
+                }
+                None => tokens.push(Token::Literal('[')),
+            },
+            '{' => match parse_braces(&chars[i + 1..]) {
+                Some((token, len)) => {
+                    tokens.push(token);
+                    i += len + 1;
+                    continue;
+                }
+                None => tokens.push(Token::Literal('{')),
+            },
+            c => tokens.push(Token::Literal(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// Parses the inside of `[...]`. Returns the token and the number of
+/// characters consumed, including the closing bracket.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let end = chars.iter().position(|x| *x == ']')?;
+    let (negated, body) = match chars[..end].split_first() {
+        Some(('!', body)) => (true, body),
+        _ => (false, &chars[..end]),
+    };
+    let mut ranges = vec![];
+    let mut i = 0;
+    while i < body.len() {
+        if i + 2 < body.len() && body[i + 1] == '-' {
+            ranges.push((body[i], body[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((body[i], body[i]));
+            i += 1;
+        }
+    }
+    Some((Token::Class(negated, ranges), end + 1))
+}
+
+/// Parses the inside of `{...}`. Returns `None` if the braces are not closed
+/// or contain neither a `,` nor a numeric range, in which case they are
+/// literal.
+fn parse_braces(chars: &[char]) -> Option<(Token, usize)> {
+    let mut depth = 0;
+    let mut commas = vec![];
+    let mut end = None;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' if depth == 0 => {
+                end = Some(i);
+                break;
+            }
+            '}' => depth -= 1,
+            ',' if depth == 0 => commas.push(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    let end = end?;
+    let body = &chars[..end];
+
+    if commas.is_empty() {
+        let body: String = body.iter().collect();
+        let (start, stop) = body.split_once("..")?;
+        let token = Token::Range(start.parse().ok()?, stop.parse().ok()?);
+        return Some((token, end + 1));
+    }
+
+    let mut alternatives = vec![];
+    let mut start = 0;
+    for comma in commas.into_iter().chain([end]) {
+        alternatives.push(parse_tokens(&body[start..comma]));
+        start = comma + 1;
+    }
+    Some((Token::Alternatives(alternatives), end + 1))
+}
+
+fn matches(tokens: &[Token], text: &[char]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return text.is_empty();
+    };
+    match token {
+        Token::Literal(c) => {
+            text.first() == Some(c) && matches(rest, &text[1..])
+        }
+        Token::One => {
+            text.first().is_some_and(|x| *x != '/') && matches(rest, &text[1..])
+        }
+        Token::Class(negated, ranges) => text.first().is_some_and(|c| {
+            let in_class = ranges.iter().any(|(a, b)| (a..=b).contains(&c));
+            *c != '/' && in_class != *negated && matches(rest, &text[1..])
+        }),
+        Token::Any => (0..=text.len())
+            .take_while(|i| *i == 0 || text[i - 1] != '/')
+            .any(|i| matches(rest, &text[i..])),
+        Token::AnyDeep => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+        Token::Alternatives(alternatives) => alternatives.iter().any(|x| {
+            let mut tokens = x.clone();
+            tokens.extend_from_slice(rest);
+            matches(&tokens, text)
+        }),
+        Token::Range(start, stop) => {
+            let sign = usize::from(text.first() == Some(&'-'));
+            let digits = text[sign..]
+                .iter()
+                .take_while(|x| x.is_ascii_digit())
+                .count();
+            let len = sign + digits;
+            let number: String = text[..len].iter().collect();
+            digits > 0
+                && number
+                    .parse::<i64>()
+                    .is_ok_and(|x| (*start..=*stop).contains(&x))
+                && matches(rest, &text[len..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_root_in_preamble() {
+        assert!(EditorConfig::parse("root = true\n").root);
+        assert!(EditorConfig::parse("ROOT=TRUE\n").root);
+        assert!(EditorConfig::parse("# comment\n\nRoot = True\n[*]\n").root);
+        assert!(!EditorConfig::parse("root = false\n").root);
+        assert!(!EditorConfig::parse("[*]\nroot = true\n").root);
+    }
+
+    #[test]
+    fn parses_sections_and_properties() {
+        let config = EditorConfig::parse(
+            "root=true\n; comment\n[*]\nIndent_Style = space\ninvalid\n\
+             [*.md]\nindent_style = tab\n",
+        );
+        assert_eq!(config.sections.len(), 2);
+        assert_eq!(config.sections[1].glob, "*.md");
+        let properties = config.properties(Path::new("doc/README.md"));
+        assert_eq!(properties["indent_style"], "tab");
+        let properties = config.properties(Path::new("main.rs"));
+        assert_eq!(properties["indent_style"], "space");
+    }
+
+    #[test]
+    fn glob_without_slash_matches_in_any_directory() {
+        let section = Section::new("*.c");
+        assert!(section.matches("a.c"));
+        assert!(section.matches("src/deep/a.c"));
+        assert!(!section.matches("a.h"));
+    }
+
+    #[test]
+    fn glob_with_slash_is_relative() {
+        let section = Section::new("lib/*.c");
+        assert!(section.matches("lib/a.c"));
+        assert!(!section.matches("src/lib/a.c"));
+        assert!(!section.matches("lib/sub/a.c"));
+        assert!(Section::new("/top.c").matches("top.c"));
+        assert!(!Section::new("/top.c").matches("sub/top.c"));
+        assert!(Section::new("lib/**.c").matches("lib/sub/a.c"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        let section = Section::new("a?.c");
+        assert!(section.matches("ab.c"));
+        assert!(!section.matches("a.c"));
+        assert!(!section.matches("abc.c"));
+    }
+
+    #[test]
+    fn brace_alternatives() {
+        let section = Section::new("*.{c,h,{cc,cpp}}");
+        assert!(section.matches("a.c"));
+        assert!(section.matches("a.h"));
+        assert!(section.matches("a.cpp"));
+        assert!(!section.matches("a.rs"));
+        assert!(Section::new("{single}").matches("{single}"));
+        assert!(Section::new("{open").matches("{open"));
+    }
+
+    #[test]
+    fn numeric_ranges() {
+        let section = Section::new("file{1..3}.txt");
+        assert!(section.matches("file1.txt"));
+        assert!(section.matches("file3.txt"));
+        assert!(!section.matches("file4.txt"));
+        assert!(!section.matches("file.txt"));
+        assert!(Section::new("x{-2..2}").matches("x-1"));
+        assert!(!Section::new("x{-2..2}").matches("x-3"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(Section::new("[ab].c").matches("a.c"));
+        assert!(!Section::new("[ab].c").matches("c.c"));
+        assert!(Section::new("[a-c].c").matches("b.c"));
+        assert!(Section::new("[!a-c].c").matches("d.c"));
+        assert!(!Section::new("[!a-c].c").matches("b.c"));
+        assert!(Section::new("[a").matches("[a"));
+    }
+
+    #[test]
+    fn escaping() {
+        assert!(Section::new("\\*.c").matches("*.c"));
+        assert!(!Section::new("\\*.c").matches("a.c"));
+        assert!(Section::new("\\{a,b\\}").matches("{a,b}"));
+        assert!(!Section::new("\\{a,b\\}").matches("a"));
+        assert!(Section::new("\\[x]").matches("[x]"));
     }
 }
@@ -0,0 +1,108 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{diagnostics::Diagnostic, Error, Result};
+
+/// Where events go, if `-mJ` is given.
+static SINK: Mutex<Option<File>> = Mutex::new(None);
+
+/// The state changes of mk, written as one JSON object per line for tools
+/// that follow a build.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Project {
+        project_dir: &'a Path,
+        build_system: &'a str,
+        modes: &'a [String],
+        build_dir: &'a Path,
+        root_reason: &'a str,
+    },
+    ConfigureStart {
+        project_dir: &'a Path,
+    },
+    ConfigureEnd {
+        project_dir: &'a Path,
+        #[serde(flatten)]
+        status: Status,
+    },
+    BuildStart {
+        project_dir: &'a Path,
+    },
+    BuildEnd {
+        project_dir: &'a Path,
+        #[serde(flatten)]
+        status: Status,
+        errors: usize,
+        warnings: usize,
+    },
+    Diagnostic {
+        project_dir: &'a Path,
+        #[serde(flatten)]
+        diagnostic: &'a Diagnostic,
+    },
+    Watch {
+        paths: &'a [PathBuf],
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub success: bool,
+    /// Not set if the process was killed by a signal.
+    pub exit_code: Option<i32>,
+    /// In seconds.
+    pub duration: f64,
+}
+
+impl Status {
+    pub fn new(status: ExitStatus, duration: Duration) -> Self {
+        Self {
+            success: status.success(),
+            exit_code: status.code(),
+            duration: duration.as_secs_f64(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    /// Seconds since the Unix epoch.
+    time: f64,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+/// Sends events to the file at `path`, which can also be a pipe such as
+/// `/dev/fd/3`.
+pub fn open(path: &Path) -> Result<()> {
+    let file = File::create(path).map_err(|e| Error::Io(path.into(), e))?;
+    *SINK.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    SINK.lock().unwrap().is_some()
+}
+
+pub fn emit(event: Event) {
+    let mut sink = SINK.lock().unwrap();
+    let Some(file) = sink.as_mut() else {
+        return;
+    };
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |x| x.as_secs_f64());
+    let mut line = serde_json::to_string(&Line { time, event }).unwrap();
+    line.push('\n');
+    // A reader that went away must not stop the build.
+    let _ = file.write_all(line.as_bytes());
+}
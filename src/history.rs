@@ -0,0 +1,281 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{project::Project, summary::Summary, Error, Result};
+
+const HISTORY_FILE: &str = "history.jsonl";
+/// How many runs `mk -m history` shows.
+const SHOWN_RUNS: usize = 20;
+/// How many earlier runs a build time is compared with.
+const COMPARED_RUNS: usize = 10;
+
+/// The directory for state that outlives builds, `$XDG_STATE_HOME/mk` or
+/// `~/.local/state/mk`.
+pub fn state_dir() -> Option<PathBuf> {
+    let state_home = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state_home.join("mk"))
+}
+
+/// A build, as recorded in the history file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub project_dir: PathBuf,
+    pub build_system: String,
+    pub modes: Vec<String>,
+    pub args: Vec<String>,
+    /// Seconds spent in the configure step, if it ran.
+    pub configure_time: Option<f64>,
+    /// Seconds spent in the build, if it ran.
+    pub build_time: Option<f64>,
+    /// Not set if the build was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub errors: usize,
+    pub warnings: usize,
+    pub git_head: Option<String>,
+}
+
+fn git_head(dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(dir)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let head = String::from_utf8(output.stdout).ok()?;
+    output.status.success().then(|| head.trim().to_string())
+}
+
+impl Record {
+    pub fn new(project: &Project, summary: &Summary) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        Self {
+            timestamp,
+            project_dir: project.project_dir.clone(),
+            build_system: summary.build_system.to_string(),
+            modes: summary.modes.clone(),
+            args: project.args.clone(),
+            configure_time: summary.configure_time.map(|x| x.as_secs_f64()),
+            build_time: summary.build_time.map(|x| x.as_secs_f64()),
+            exit_code: summary.status.code(),
+            errors: summary.errors,
+            warnings: summary.warnings,
+            git_head: git_head(&project.project_dir),
+        }
+    }
+
+    fn is_comparable(&self, other: &Record) -> bool {
+        self.project_dir == other.project_dir
+            && self.modes == other.modes
+            && self.args == other.args
+            && other.exit_code == Some(0)
+            && other.build_time.is_some()
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    state_dir().map(|x| x.join(HISTORY_FILE))
+}
+
+/// Appends `record` to the history file.
+pub fn append(record: &Record) -> Result<()> {
+    let Some(path) = history_path() else {
+        return Ok(());
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| Error::Io(dir.into(), e))?;
+    }
+    let mut line = serde_json::to_string(record).unwrap();
+    line.push('\n');
+    // A single write, so records of concurrent builds don't mix.
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(|e| Error::Io(path, e))
+}
+
+/// Reads the history file, skipping lines that cannot be parsed.
+pub fn load() -> Result<Vec<Record>> {
+    let Some(path) = history_path() else {
+        return Ok(vec![]);
+    };
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(Error::Io(path, e)),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|x| serde_json::from_str(x).ok())
+        .collect())
+}
+
+fn format_time(timestamp: u64) -> String {
+    let time = timestamp as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return timestamp.to_string();
+    }
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min
+    )
+}
+
+fn format_seconds(seconds: Option<f64>) -> String {
+    seconds.map_or("-".to_string(), |x| format!("{:.1}s", x))
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let middle = values.len() / 2;
+    match values.len() {
+        0 => None,
+        n if n % 2 == 0 => Some((values[middle - 1] + values[middle]) / 2.0),
+        _ => Some(values[middle]),
+    }
+}
+
+/// Compares the build time of `records[index]` with the median of the
+/// earlier successful builds with the same modes and arguments. Returns the
+/// median if the build was notably slower.
+fn slower_than_usual(records: &[&Record], index: usize) -> Option<f64> {
+    let record = records[index];
+    let build_time = record.build_time?;
+    let earlier = records[..index]
+        .iter()
+        .rev()
+        .filter(|x| record.is_comparable(x))
+        .take(COMPARED_RUNS)
+        .filter_map(|x| x.build_time)
+        .collect();
+    let median = median(earlier)?;
+    (build_time > median * 1.5 && build_time - median > 1.0).then_some(median)
+}
+
+/// Prints the recent builds of the project in `project_dir`, flagging those
+/// that took notably longer than usual.
+pub fn print(project_dir: &Path) -> Result<()> {
+    let records = load()?;
+    let records: Vec<&Record> = records
+        .iter()
+        .filter(|x| x.project_dir == project_dir)
+        .collect();
+    let start = records.len().saturating_sub(SHOWN_RUNS);
+    for (index, record) in records.iter().enumerate().skip(start) {
+        let status = match record.exit_code {
+            Some(code) => format!("exit {}", code),
+            None => "killed".to_string(),
+        };
+        let head = record.git_head.as_deref().unwrap_or("-");
+        let counts =
+            format!("{} errors, {} warnings", record.errors, record.warnings);
+        let mut line = format!(
+            "{}  {:7.7}  {:10}  configure {:>6}  build {:>7}  {:7}  {}",
+            format_time(record.timestamp),
+            head,
+            record.modes.join(" "),
+            format_seconds(record.configure_time),
+            format_seconds(record.build_time),
+            status,
+            counts,
+        );
+        if let Some(median) = slower_than_usual(&records, index) {
+            line.push_str(&format!(
+                "  slower than usual (median {:.1}s)",
+                median
+            ));
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(build_time: f64) -> Record {
+        Record {
+            timestamp: 0,
+            project_dir: PathBuf::from("/src/app"),
+            build_system: "make".into(),
+            modes: vec!["default".into()],
+            args: vec![],
+            configure_time: None,
+            build_time: Some(build_time),
+            exit_code: Some(0),
+            errors: 0,
+            warnings: 0,
+            git_head: None,
+        }
+    }
+
+    fn slower(records: &[Record]) -> Option<f64> {
+        let records: Vec<&Record> = records.iter().collect();
+        slower_than_usual(&records, records.len() - 1)
+    }
+
+    #[test]
+    fn median_of_odd_and_even_counts() {
+        assert_eq!(median(vec![]), None);
+        assert_eq!(median(vec![3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(median(vec![4.0, 1.0, 3.0, 2.0]), Some(2.5));
+    }
+
+    #[test]
+    fn flags_builds_well_above_the_median() {
+        let records = [record(10.0), record(100.0), record(12.0), record(19.0)];
+        assert_eq!(slower(&records), Some(12.0));
+        // Not 1.5 times the median.
+        let records = [record(10.0), record(12.0), record(16.0)];
+        assert_eq!(slower(&records), None);
+        // 1.5 times the median, but less than a second.
+        let records = [record(1.0), record(1.0), record(1.9)];
+        assert_eq!(slower(&records), None);
+        // Nothing to compare with.
+        assert_eq!(slower(&[record(100.0)]), None);
+    }
+
+    #[test]
+    fn compares_only_similar_successful_builds() {
+        let failed = Record {
+            exit_code: Some(2),
+            ..record(100.0)
+        };
+        let other_mode = Record {
+            modes: vec!["release".into()],
+            ..record(100.0)
+        };
+        let records = [record(10.0), failed, other_mode, record(16.0)];
+        assert_eq!(slower(&records), Some(10.0));
+    }
+
+    #[test]
+    fn compares_only_recent_builds() {
+        let mut records: Vec<_> = (0..5).map(|_| record(1.0)).collect();
+        records.extend((0..COMPARED_RUNS).map(|_| record(10.0)));
+        records.push(record(16.0));
+        assert_eq!(slower(&records), Some(10.0));
+    }
+}
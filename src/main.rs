@@ -1,18 +1,37 @@
 use std::{
     io::{self, Write},
-    path::PathBuf,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
     process::ExitStatus,
     thread::sleep,
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 pub mod build_system;
+pub mod ci;
+pub mod condition;
+pub mod dependencies;
+pub mod diagnostics;
 pub mod editor_config;
+pub mod events;
+pub mod history;
 pub mod mk_info;
+pub mod monorepo;
 pub mod project;
+pub mod scheduler;
+pub mod summary;
+pub mod validate;
+pub mod variables;
 
+use ci::Service;
+use dependencies::Graph;
+use events::Event;
+use history::Record;
 use libc::isatty;
+use mk_info::MkInfo;
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 use project::Project;
+use scheduler::Scheduler;
+use summary::Summary;
 use thiserror::Error;
 
 static HELP: &str = r#"Usage: mk [options] [build system args]
@@ -24,6 +43,21 @@ Options:
     -mC <dir>: Change the current working directory [default: .]
     -mB <dir>: Change the build directory [default: build]
     -mp: try to build in a container. Needs podman or docker installed
+    -mn: Print the commands instead of running them
+    -mv: Explain how the project root was found
+    -mm <mode>: Select a mode from the Mk file. Can be given multiple times
+    -mM: List the modes defined in the Mk file
+    -ma: Build all projects below the project root
+    -mj <jobs>: Build up to <jobs> independent projects at once [default: 1]
+    -mJ, --m-json <path>: Write events as JSON lines to <path>
+    -mi: Write GitHub Actions annotations. Detected from GITHUB_ACTIONS
+    -m <command>: Run a command instead of building
+
+Commands:
+    schema: Print the JSON Schema of the Mk file format
+    config: Print the resolved configuration as YAML
+    config-json: Print the resolved configuration as JSON
+    history: Show the recent builds of the project
 
 Supported build systems:
 meson/ninja
@@ -44,23 +78,89 @@ pub enum Error {
     Io(PathBuf, io::Error),
     #[error("{0}: {1}")]
     Command(String, io::Error),
+    #[error("{0}: {}", yaml_error_message(.1))]
+    SerdeYaml(PathBuf, serde_yaml::Error),
     #[error("{0}: {1}")]
-    SerdeIni(PathBuf, serde_ini::de::Error),
+    Toml(PathBuf, toml::de::Error),
     #[error("{0}: {1}")]
-    SerdeYaml(PathBuf, serde_yaml::Error),
+    SerdeJson(PathBuf, serde_json::Error),
     #[error("{0}")]
     Notify(#[from] notify::Error),
+    #[error("Unknown command: {0}")]
+    UnknownCommand(String),
     #[error("Missing Argument for {0}")]
     MissingArgument(&'static str),
     #[error("No container runtime found")]
     NoContainerRuntimeFound,
     #[error("Missing container image")]
     MissingContainerImage,
+    #[error("Unknown mode: {0}")]
+    UnknownMode(String),
+    #[error("Cyclic mode inheritance: {0}")]
+    ModeCycle(String),
+    #[error("{0}: {1}")]
+    Include(PathBuf, Box<Error>),
+    #[error("Included file not found: {0}")]
+    IncludeNotFound(String),
+    #[error("Cyclic include of {0}")]
+    IncludeCycle(PathBuf),
+    #[error("Unknown variable: {0}")]
+    UnknownVariable(String),
+    #[error("Unterminated variable reference: {0}")]
+    UnterminatedVariable(String),
+    #[error("Dependency {0}: {1}")]
+    Dependency(PathBuf, Box<Error>),
+    #[error("Cyclic dependency: {0}")]
+    DependencyCycle(String),
+    #[error("Dependency {0} failed")]
+    DependencyFailed(PathBuf),
+    #[error("Invalid argument for {0}: {1}")]
+    InvalidArgument(&'static str, String),
+    #[error("Jobserver: {0}")]
+    Jobserver(io::Error),
+    #[error("Refusing to remove {0}: not a build directory of the project")]
+    UnsafeBuildDir(PathBuf),
+    #[error("Build failed with {0}")]
+    BuildFailed(ExitStatus),
+    #[error("{0} of {1} projects failed")]
+    ProjectsFailed(usize, usize),
+}
+
+/// serde_yaml leaves out the position if an error is on the very first
+/// character, which is where the first key of a Mk file usually is.
+fn yaml_error_message(error: &serde_yaml::Error) -> String {
+    let message = error.to_string();
+    match error.location() {
+        Some(l) if l.line() == 1 && l.column() == 1 => {
+            format!("{} at line 1 column 1", message)
+        }
+        _ => message,
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
+enum MkCommand {
+    Schema,
+    Config,
+    ConfigJson,
+    History,
+}
+
+impl MkCommand {
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "schema" => Ok(Self::Schema),
+            "config" => Ok(Self::Config),
+            "config-json" => Ok(Self::ConfigJson),
+            "history" => Ok(Self::History),
+            _ => Err(Error::UnknownCommand(name.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Opts {
     args: Vec<String>,
     build_dir: Option<PathBuf>,
@@ -69,6 +169,15 @@ pub struct Opts {
     reconfigure: bool,
     watch: bool,
     container: bool,
+    dry_run: bool,
+    verbose: bool,
+    modes: Vec<String>,
+    list_modes: bool,
+    all: bool,
+    jobs: usize,
+    json: Option<PathBuf>,
+    ci: Option<Service>,
+    command: Option<MkCommand>,
 }
 
 impl Opts {
@@ -80,6 +189,15 @@ impl Opts {
         let mut reconfigure = false;
         let mut watch = false;
         let mut container = false;
+        let mut dry_run = false;
+        let mut verbose = false;
+        let mut modes = vec![];
+        let mut list_modes = false;
+        let mut all = false;
+        let mut jobs = 1;
+        let mut json = None;
+        let mut ci = Service::detect();
+        let mut command = None;
 
         let mut args_iter = std::env::args().skip(1);
         let mut is_first = true;
@@ -97,6 +215,38 @@ impl Opts {
                 "-mc" => clean = true,
                 "-mR" => reconfigure = true,
                 "-mp" => container = true,
+                "-mn" => dry_run = true,
+                "-mv" => verbose = true,
+                "-mM" => list_modes = true,
+                "-ma" => all = true,
+                "-mi" => ci = Some(Service::GitHubActions),
+                "-mj" => {
+                    let arg = args_iter
+                        .next()
+                        .ok_or(Error::MissingArgument("-mj"))?;
+                    jobs = match arg.parse() {
+                        Ok(jobs) if jobs > 0 => jobs,
+                        _ => return Err(Error::InvalidArgument("-mj", arg)),
+                    };
+                }
+                "-mm" => modes.push(
+                    args_iter.next().ok_or(Error::MissingArgument("-mm"))?,
+                ),
+                "-m" => {
+                    command = Some(MkCommand::parse(
+                        &args_iter
+                            .next()
+                            .ok_or(Error::MissingArgument("-m"))?,
+                    )?)
+                }
+                "-mJ" | "--m-json" => {
+                    json = Some(
+                        args_iter
+                            .next()
+                            .ok_or(Error::MissingArgument("-mJ"))?
+                            .into(),
+                    )
+                }
                 "-mC" => {
                     cwd = args_iter
                         .next()
@@ -122,92 +272,363 @@ impl Opts {
 
         Ok(Self {
             container,
+            dry_run,
+            verbose,
             args,
             build_dir,
             clean,
             cwd,
             reconfigure,
             watch,
+            modes,
+            list_modes,
+            all,
+            jobs,
+            json,
+            ci,
+            command,
         })
     }
 }
 
 fn try_main() -> Result<()> {
     let opts = Opts::parse()?;
+    if let Some(path) = &opts.json {
+        events::open(path)?;
+    }
+
+    if let Some(MkCommand::Schema) = opts.command {
+        let schema = schemars::schema_for!(MkInfo);
+        println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+        return Ok(());
+    }
+
+    if opts.all {
+        return build_all(&opts);
+    }
+
     let project = Project::from_opts(&opts)?;
 
+    match opts.command {
+        Some(MkCommand::Config) => {
+            let description = project.describe()?;
+            print!("{}", serde_yaml::to_string(&description).unwrap());
+            return Ok(());
+        }
+        Some(MkCommand::ConfigJson) => {
+            let description = project.describe()?;
+            println!("{}", serde_json::to_string_pretty(&description).unwrap());
+            return Ok(());
+        }
+        Some(MkCommand::History) => {
+            return history::print(&project.project_dir);
+        }
+        _ => {}
+    }
+
+    if opts.list_modes {
+        list_modes(&project);
+        return Ok(());
+    }
+
     if opts.clean {
         return project.clean();
     }
 
+    let project_dir = project.project_dir.clone();
+    let mut graph = dependencies::resolve(vec![project], &opts)?;
+    announce(&graph);
+    let scheduler = Scheduler::new(opts.jobs)?;
+    scheduler.prepare(&mut graph, |dir| {
+        dir.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    });
+    let run = |graph| run(graph, &scheduler, &opts);
+
     if opts.watch {
-        run(&project, &opts)?;
+        run(&graph)?;
         let (tx, rx) = std::sync::mpsc::channel();
         let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
-        watcher.watch(&project.project_dir, RecursiveMode::Recursive)?;
+        watcher.watch(&project_dir, RecursiveMode::Recursive)?;
 
         let threshold = Duration::from_millis(100);
         let mut last_build_time = SystemTime::now();
-        for _ in rx {
+        for event in rx {
             if last_build_time + threshold < SystemTime::now() {
+                if let Ok(event) = &event {
+                    events::emit(Event::Watch {
+                        paths: &event.paths,
+                    });
+                }
                 sleep(threshold);
-                run(&project, &opts)?;
+                run(&graph)?;
                 last_build_time = SystemTime::now();
             }
         }
     } else {
-        run(&project, &opts)?;
+        let status = run(&graph)?;
+        if !status.success() {
+            return Err(Error::BuildFailed(status));
+        }
     }
 
     Ok(())
 }
 
-fn report(status: ExitStatus) {
-    let Some((cols, rows)) = term_size::dimensions() else {
+fn list_modes(project: &Project) {
+    let modes = project.mk_info.modes();
+    let width = modes.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, info) in modes {
+        let active = if project.modes.iter().any(|x| x == name) {
+            '*'
+        } else {
+            ' '
+        };
+        let description = info.description.as_deref().unwrap_or_default();
+        let line = format!("{} {:width$}  {}", active, name, description);
+        println!("{}", line.trim_end());
+    }
+}
+
+fn announce(graph: &Graph) {
+    for project in &graph.projects {
+        events::emit(Event::Project {
+            project_dir: &project.project_dir,
+            build_system: project.build_system.name(),
+            modes: &project.modes,
+            build_dir: &project.build_dir,
+            root_reason: &project.root_reason,
+        });
+    }
+}
+
+/// Draws the summary in the last line of the terminal.
+fn report(summary: &Summary) {
+    let Some((_, rows)) = term_size::dimensions() else {
         return;
     };
     print!("\x1b[s\x1b[7l");
-    print!("\x1b[{};{}H", rows, cols - 2);
-    if status.success() {
-        print!("✅");
-    } else {
-        print!("❌");
-    }
+    print!("\x1b[{};1H\x1b[2K{}", rows, summary);
     print!("\x1b[u\x1b[7h");
     std::io::stdout().flush().unwrap();
 }
 
-fn run(project: &Project, opts: &Opts) -> Result<()> {
+/// Builds the projects of `graph` and prints a summary of each. In watch
+/// mode, the first failure or the last project is reported instead. Returns
+/// the status of the first failed build.
+fn run(
+    graph: &Graph,
+    scheduler: &Scheduler,
+    opts: &Opts,
+) -> Result<ExitStatus> {
     // Clear the screen if we're running in watch mode
-    if opts.watch && unsafe { isatty(1) } != 0 {
+    if opts.watch && opts.ci.is_none() && unsafe { isatty(1) } != 0 {
         let mut out = io::stdout();
         let _ = out.write_all(b"\x1b[H\x1b[2J\x1b[3J");
         let _ = out.flush();
     }
 
-    let conf_result = if opts.reconfigure || !project.is_configured()? {
-        project.clean()?;
-        project.configure()?
-    } else {
-        ExitStatus::default()
+    let results = scheduler.run(graph, |project| {
+        if graph.projects.len() > 1 {
+            eprintln!("mk: {}", project.project_dir.display());
+        }
+        let summary = build(project, opts)?;
+        if !opts.watch {
+            eprintln!("{}", summary);
+        }
+        Ok(summary)
+    });
+    let mut reported: Option<Summary> = None;
+    for summary in results {
+        let summary = match summary {
+            Err(Error::DependencyFailed(_)) => continue,
+            summary => summary?,
+        };
+        if reported.as_ref().is_none_or(|x| x.success()) {
+            reported = Some(summary);
+        }
+    }
+
+    let status = reported
+        .as_ref()
+        .map_or(ExitStatus::default(), |x| x.status);
+    // CI logs have no last line to draw on.
+    if let Some(summary) = reported.filter(|_| opts.watch && opts.ci.is_none())
+    {
+        report(&summary);
+    }
+
+    Ok(status)
+}
+
+fn build(project: &Project, opts: &Opts) -> Result<Summary> {
+    let project_dir = &project.project_dir;
+    let github = opts.ci == Some(Service::GitHubActions);
+    // Groups of concurrent builds would interleave.
+    let grouped = github && opts.jobs == 1;
+    project.clear_diagnostics();
+    let mut summary = Summary::new(project);
+    if opts.reconfigure || !project.is_configured()? {
+        events::emit(Event::ConfigureStart { project_dir });
+        if grouped {
+            ci::group(&format!("Configure {}", project_dir.display()));
+        }
+        let start = Instant::now();
+        let status = project.clean().and_then(|_| project.configure());
+        if grouped {
+            ci::end_group();
+        }
+        summary.status = status?;
+        summary.configure_time = Some(start.elapsed());
+        let status = events::Status::new(summary.status, start.elapsed());
+        events::emit(Event::ConfigureEnd {
+            project_dir,
+            status,
+        });
+    }
+
+    if summary.success() {
+        project.link_compile_commands()?;
+        events::emit(Event::BuildStart { project_dir });
+        if grouped {
+            ci::group(&format!("Build {}", project_dir.display()));
+        }
+        let start = Instant::now();
+        let status = project.build();
+        if grouped {
+            ci::end_group();
+        }
+        summary.status = status?;
+        summary.build_time = Some(start.elapsed());
+        summary.count_diagnostics(project);
+        events::emit(Event::BuildEnd {
+            project_dir,
+            status: events::Status::new(summary.status, start.elapsed()),
+            errors: summary.errors,
+            warnings: summary.warnings,
+        });
+    }
+    project.write_diagnostics()?;
+    summary.count_diagnostics(project);
+    if github {
+        for diagnostic in project.resolved_diagnostics() {
+            println!("{}", ci::annotation(&diagnostic, &project.work_dir));
+        }
+    }
+    if !opts.dry_run {
+        if let Err(e) = history::append(&Record::new(project, &summary)) {
+            eprintln!("Warning: Could not record the build: {}", e);
+        }
+    }
+    Ok(summary)
+}
+
+/// Builds every project below the project root and their dependencies and
+/// prints a summary. Projects whose dependencies failed are skipped.
+fn build_all(opts: &Opts) -> Result<()> {
+    let work_dir = opts
+        .cwd
+        .canonicalize()
+        .map_err(|e| Error::Io(opts.cwd.clone(), e))?;
+    let root = match project::find_root(&work_dir, opts.verbose) {
+        Ok(root_info) => root_info.project_dir,
+        Err(Error::NoProjectRootFound) => work_dir,
+        Err(e) => return Err(e),
     };
 
-    let result = if conf_result.success() {
-        project.build()?
-    } else {
-        conf_result
+    let mut results = vec![];
+    let mut projects = vec![];
+    for dir in monorepo::find_projects(&root)? {
+        let opts = Opts {
+            cwd: dir.clone(),
+            ..opts.clone()
+        };
+        match Project::from_opts(&opts) {
+            Ok(project) => projects.push(project),
+            Err(e) => results.push((dir, Err(e))),
+        }
+    }
+    let build_dirs = monorepo::build_dirs(&projects);
+    let in_build_dir =
+        |dir: &Path| build_dirs.iter().any(|x| dir.starts_with(x));
+    projects.retain(|x| !in_build_dir(&x.project_dir));
+    results.retain(|(dir, _)| !in_build_dir(dir));
+    let mut graph = dependencies::resolve(projects, opts)?;
+    announce(&graph);
+
+    let name = |dir: &Path| match dir.strip_prefix(&root) {
+        Ok(name) if name.as_os_str().is_empty() => PathBuf::from("."),
+        Ok(name) => name.to_path_buf(),
+        Err(_) => dir.to_path_buf(),
     };
+    let scheduler = Scheduler::new(opts.jobs)?;
+    scheduler.prepare(&mut graph, |dir| name(dir).display().to_string());
+    let statuses = scheduler.run(&graph, |project| {
+        eprintln!("mk: {}", name(&project.project_dir).display());
+        if opts.clean {
+            project.clean().map(|_| Summary::new(project))
+        } else {
+            build(project, opts)
+        }
+    });
+    for (project, result) in graph.projects.iter().zip(statuses) {
+        let result = match result {
+            Err(Error::DependencyFailed(dir)) => {
+                Err(Error::DependencyFailed(name(&dir)))
+            }
+            result => result,
+        };
+        results.push((project.project_dir.clone(), result));
+    }
 
-    if opts.watch {
-        report(result);
+    eprintln!();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut failed = 0;
+    for (dir, result) in &results {
+        let name = name(dir);
+        match result {
+            Ok(summary) => {
+                if !summary.success() {
+                    failed += 1;
+                }
+                let details = summary.details();
+                eprintln!("{} {}: {}", summary.glyph(), name.display(), details)
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("❌ {}: {}", name.display(), e)
+            }
+        }
     }
 
-    Ok(())
+    if failed > 0 {
+        Err(Error::ProjectsFailed(failed, results.len()))
+    } else {
+        Ok(())
+    }
+}
+
+/// The exit code for a failed build, as a shell would report it.
+fn exit_code(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    }
 }
 
 fn main() {
-    if let Err(e) = try_main() {
-        eprintln!("{}", e);
-        std::process::exit(1);
+    match try_main() {
+        Ok(()) => {}
+        // The summary already reported the failure.
+        Err(Error::BuildFailed(status)) => {
+            std::process::exit(exit_code(status))
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
@@ -1,49 +1,183 @@
 use std::{
     collections::HashMap,
-    fs::File,
+    env, fs,
+    marker::PhantomData,
     path::{Path, PathBuf},
 };
 
-use crate::{Error, Result};
+use crate::{
+    condition::Condition, validate::Validator, variables::Variables, Error,
+    Result,
+};
 use same_file::is_same_file;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{
+    de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, Visitor},
+    Deserialize, Serialize,
+};
+
+pub static MKINFO_LOCAL_FILES: &[&str] = &[
+    ".Mk.local.yaml",
+    ".Mk.local.yml",
+    ".Mk.local.toml",
+    ".Mk.local.json",
+    ".mk.local.yaml",
+    ".mk.local.yml",
+    ".mk.local.toml",
+    ".mk.local.json",
+];
+
+pub static USER_CONFIG_FILES: &[&str] =
+    &["config.yaml", "config.yml", "config.toml", "config.json"];
 
 pub static MKINFO_FILES: &[&str] = &[
     ".Mk",
     ".Mk.yaml",
     ".Mk.yml",
+    ".Mk.toml",
+    ".Mk.json",
     ".github/mk",
     ".github/mk.yaml",
     ".github/mk.yml",
+    ".github/mk.toml",
+    ".github/mk.json",
     ".github/Mk",
     ".github/Mk.yaml",
     ".github/Mk.yml",
+    ".github/Mk.toml",
+    ".github/Mk.json",
     ".mk",
     ".mk.yaml",
     ".mk.yml",
+    ".mk.toml",
+    ".mk.json",
     "Mk",
     "Mk.yaml",
     "Mk.yml",
+    "Mk.toml",
+    "Mk.json",
 ];
 
-#[derive(Debug, Deserialize, Default)]
+/// The table in `Cargo.toml` that can hold the Mk configuration.
+static CARGO_METADATA_PATH: &[&str] = &["package", "metadata", "mk"];
+
+enum Format {
+    Yaml,
+    Toml,
+    Json,
+    CargoToml,
+}
+
+impl Format {
+    fn of(path: &Path) -> Self {
+        if path.file_name().is_some_and(|x| x == "Cargo.toml") {
+            return Format::CargoToml;
+        }
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("toml") => Format::Toml,
+            Some("json") => Format::Json,
+            _ => Format::Yaml,
+        }
+    }
+}
+
+/// Deserializes `seed` from the value at `path` in nested maps and skips
+/// everything else. Yields `None` if there is no such value.
+struct AtPath<'a, S> {
+    path: &'a [&'a str],
+    seed: S,
+}
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for AtPath<'_, S> {
+    type Value = Option<S::Value>;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<Self::Value, D::Error> {
+        if self.path.is_empty() {
+            self.seed.deserialize(deserializer).map(Some)
+        } else {
+            deserializer.deserialize_map(self)
+        }
+    }
+}
+
+impl<'de, S: DeserializeSeed<'de>> Visitor<'de> for AtPath<'_, S> {
+    type Value = Option<S::Value>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a table containing `{}`", self.path[0])
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> std::result::Result<Self::Value, A::Error> {
+        let mut seed = Some(self.seed);
+        let mut value = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match seed.take() {
+                Some(x) if key == self.path[0] => {
+                    let path = &self.path[1..];
+                    value = map.next_value_seed(AtPath { path, seed: x })?;
+                }
+                x => {
+                    seed = x;
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// Keys of `MkInfo` besides the flattened `BuildInfo`. Keep in sync with the
+/// struct, as they are used to reject unknown keys. A test checks them
+/// against the schema.
+pub static MKINFO_FIELDS: &[&str] =
+    &["mode", "default_mode", "root", "include"];
+
+/// Configuration of mk, read from `.Mk.yaml`.
+#[derive(Debug, Deserialize, JsonSchema, Default, Clone)]
+#[schemars(deny_unknown_fields)]
 pub struct MkInfo {
     #[serde(flatten)]
     pub base: BuildInfo,
+    /// Named modes that are merged into the configuration when selected.
     pub mode: Option<HashMap<String, BuildInfo>>,
+    /// Modes used if none are selected.
+    pub default_mode: Option<String>,
+    /// Stops merging Mk files of parent directories.
+    pub root: Option<bool>,
+    /// Files that are merged below this file.
+    pub include: Option<StringOrArray>,
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// The directory for user wide configuration, `$XDG_CONFIG_HOME/mk` or
+/// `~/.config/mk`.
+pub fn user_config_dir() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("mk"))
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone)]
 #[serde(untagged)]
 pub enum ContainerDef {
     Image(String),
     Definition {
         image: String,
+        /// Extra options for the container runtime.
         opts: Option<Vec<String>>,
     },
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone)]
 #[serde(untagged)]
 pub enum StringOrArray {
     String(String),
@@ -59,27 +193,164 @@ impl From<StringOrArray> for Vec<String> {
     }
 }
 
+impl ContainerDef {
+    fn expand(self, vars: &Variables) -> Result<Self> {
+        Ok(match self {
+            ContainerDef::Image(image) => {
+                ContainerDef::Image(vars.expand(&image)?)
+            }
+            ContainerDef::Definition { image, opts } => {
+                ContainerDef::Definition {
+                    image: vars.expand(&image)?,
+                    opts: opts.map(|x| vars.expand_all(x)).transpose()?,
+                }
+            }
+        })
+    }
+}
+
+impl StringOrArray {
+    fn expand(self, vars: &Variables) -> Result<Self> {
+        Ok(match self {
+            StringOrArray::String(s) => StringOrArray::String(vars.expand(&s)?),
+            StringOrArray::Array(a) => {
+                StringOrArray::Array(vars.expand_all(a)?)
+            }
+        })
+    }
+
+    fn expand_script(self, vars: &Variables) -> Result<Self> {
+        Ok(match self {
+            StringOrArray::String(s) => {
+                StringOrArray::String(vars.expand_script(&s)?)
+            }
+            StringOrArray::Array(a) => StringOrArray::Array(
+                a.iter()
+                    .map(|x| vars.expand_script(x))
+                    .collect::<Result<_>>()?,
+            ),
+        })
+    }
+}
+
 impl Default for StringOrArray {
     fn default() -> Self {
         StringOrArray::Array(vec![])
     }
 }
 
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(
+    Debug,
+    Deserialize,
+    Serialize,
+    JsonSchema,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeStrategy {
+    #[default]
+    Append,
+    Prepend,
+    Replace,
+}
+
+/// How `BuildInfo::combine` treats the values of the upper layer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Layering {
+    /// A mode on top of the configuration. Single values are kept unless
+    /// the mode's strategies say otherwise.
+    Merge,
+    /// A configuration file or block on top of another. Single values of
+    /// the upper layer win.
+    Overlay,
+    /// A mode on top of the modes it extends. Strategies are ignored and
+    /// single values of the upper layer win.
+    Inherit,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, Clone)]
+#[schemars(deny_unknown_fields)]
 pub struct BuildInfo {
+    /// Shown next to the mode by `mk -mM`.
+    pub description: Option<String>,
+    /// Modes that are merged before this mode.
+    pub extends: Option<StringOrArray>,
+    /// How fields of this mode are merged into the configuration below it.
+    pub merge: Option<HashMap<String, MergeStrategy>>,
+    /// Ignores this mode or block unless the host matches.
+    pub when: Option<Condition>,
+    /// Blocks that are merged if their `when` matches.
+    pub conditional: Option<Vec<BuildInfo>>,
+    /// The container image used with `-mp`.
     pub container: Option<ContainerDef>,
+    /// The container runtime, `podman` or `docker` by default.
+    pub container_runtime: Option<String>,
+    /// The build directory relative to the project root.
+    pub build_dir: Option<String>,
+    /// Arguments passed to the build system if none are given.
     pub default: Option<StringOrArray>,
+    /// Arguments passed to the configure step.
     pub configure: Option<Vec<String>>,
+    /// The build system to use instead of the detected one.
     pub build_system: Option<String>,
+    /// Environment variables for the build.
     pub env: Option<HashMap<String, String>>,
+    /// Commands run before the configure step.
+    pub pre_configure: Option<Vec<StringOrArray>>,
+    /// Commands run before the build.
+    pub pre_build: Option<Vec<StringOrArray>>,
+    /// Commands run after the build, with its exit code in
+    /// `MK_BUILD_STATUS`.
+    pub post_build: Option<Vec<StringOrArray>>,
+    /// Projects that are built before this one, relative to the project
+    /// root.
+    pub depends_on: Option<Vec<String>>,
 }
 
 impl MkInfo {
     pub fn find_root_path(root_path: &Path) -> Result<Option<PathBuf>> {
-        let mut mkinfo_iter = MKINFO_FILES
+        let cargo_toml = root_path.join("Cargo.toml");
+        let cargo_toml =
+            Self::has_cargo_metadata(&cargo_toml)?.then_some(cargo_toml);
+        Self::find_path(root_path, MKINFO_FILES, cargo_toml)
+    }
+
+    fn has_cargo_metadata(path: &Path) -> Result<bool> {
+        if !path.is_file() {
+            return Ok(false);
+        }
+        let content =
+            fs::read_to_string(path).map_err(|e| Error::Io(path.into(), e))?;
+        let seed = AtPath {
+            path: CARGO_METADATA_PATH,
+            seed: PhantomData::<IgnoredAny>,
+        };
+        let value = seed
+            .deserialize(toml::Deserializer::new(&content))
+            .map_err(|e| Error::Toml(path.into(), e))?;
+        Ok(value.is_some())
+    }
+
+    /// Finds the untracked local override file next to the Mk file. It is
+    /// not checked against the files in `MKINFO_FILES` for conflicts.
+    pub fn find_local_path(root_path: &Path) -> Result<Option<PathBuf>> {
+        Self::find_path(root_path, MKINFO_LOCAL_FILES, None)
+    }
+
+    fn find_path(
+        root_path: &Path,
+        files: &[&str],
+        extra: Option<PathBuf>,
+    ) -> Result<Option<PathBuf>> {
+        let mut mkinfo_iter = files
             .iter()
             .map(|mkinfo| root_path.join(mkinfo))
-            .filter(|p| p.exists());
+            .filter(|p| p.exists())
+            .chain(extra);
 
         let path = if let Some(mkinfo) = mkinfo_iter.next() {
             mkinfo
@@ -103,9 +374,207 @@ impl MkInfo {
     }
 
     pub fn from_path(path: &Path) -> Result<Self> {
-        let reader = File::open(path).map_err(|e| Error::Io(path.into(), e))?;
-        serde_yaml::from_reader(reader)
-            .map_err(|e| Error::SerdeYaml(path.into(), e))
+        Self::load(path, &mut vec![])
+    }
+
+    fn load(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Self> {
+        let canonical =
+            path.canonicalize().map_err(|e| Error::Io(path.into(), e))?;
+        if stack.contains(&canonical) {
+            return Err(Error::IncludeCycle(canonical));
+        }
+
+        let mut mk_info = Self::parse(path)?;
+        mk_info.sources.push(path.into());
+
+        let includes: Vec<String> =
+            mk_info.include.take().unwrap_or_default().into();
+        if includes.is_empty() {
+            return Ok(mk_info);
+        }
+
+        stack.push(canonical);
+        let mut included = Self::default();
+        for include in &includes {
+            let mut include_info = Self::find_include(path, include)
+                .and_then(|x| Self::load(&x, stack))
+                .map_err(|e| Error::Include(path.into(), Box::new(e)))?;
+            // Whether the search stops is up to the including file.
+            include_info.root = None;
+            included = included.overlay(include_info);
+        }
+        stack.pop();
+
+        Ok(included.overlay(mk_info))
+    }
+
+    fn parse(path: &Path) -> Result<Self> {
+        let content =
+            fs::read_to_string(path).map_err(|e| Error::Io(path.into(), e))?;
+        match Format::of(path) {
+            Format::Yaml => Validator
+                .deserialize(serde_yaml::Deserializer::from_str(&content))
+                .and_then(|_| serde_yaml::from_str(&content))
+                .map_err(|e| Error::SerdeYaml(path.into(), e)),
+            Format::Toml => Validator
+                .deserialize(toml::Deserializer::new(&content))
+                .and_then(|_| toml::from_str(&content))
+                .map_err(|e| Error::Toml(path.into(), e)),
+            Format::Json => Validator
+                .deserialize(&mut serde_json::Deserializer::from_str(&content))
+                .and_then(|_| serde_json::from_str(&content))
+                .map_err(|e| Error::SerdeJson(path.into(), e)),
+            Format::CargoToml => {
+                let validator = AtPath {
+                    path: CARGO_METADATA_PATH,
+                    seed: Validator,
+                };
+                let mk_info = AtPath {
+                    path: CARGO_METADATA_PATH,
+                    seed: PhantomData::<Self>,
+                };
+                validator
+                    .deserialize(toml::Deserializer::new(&content))
+                    .and_then(|_| {
+                        mk_info.deserialize(toml::Deserializer::new(&content))
+                    })
+                    .map(Option::unwrap_or_default)
+                    .map_err(|e| Error::Toml(path.into(), e))
+            }
+        }
+    }
+
+    /// Resolves an included file relative to the including file, falling
+    /// back to the user config directory.
+    fn find_include(path: &Path, include: &str) -> Result<PathBuf> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        [
+            Some(dir.join(include)),
+            user_config_dir().map(|x| x.join(include)),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|x| x.is_file())
+        .ok_or_else(|| Error::IncludeNotFound(include.into()))
+    }
+
+    /// Loads the Mk file and the local override of a single directory.
+    pub fn from_dir(dir: &Path) -> Result<Self> {
+        let mut mk_info = match Self::find_root_path(dir)? {
+            Some(path) => Self::from_path(&path)?,
+            None => Self::default(),
+        };
+        if let Some(local_path) = Self::find_local_path(dir)? {
+            mk_info = mk_info.overlay(Self::from_path(&local_path)?);
+        }
+        Ok(mk_info)
+    }
+
+    /// Loads the Mk files of all directories from `work_dir` up to
+    /// `project_dir`. Files closer to `work_dir` are merged last. A file with
+    /// `root: true` stops the search, like in `.editorconfig`.
+    pub fn from_dirs(project_dir: &Path, work_dir: &Path) -> Result<Self> {
+        let mut layers = vec![];
+        for dir in work_dir.ancestors() {
+            let layer = Self::from_dir(dir)?;
+            let is_root = layer.root.unwrap_or(false);
+            layers.push(layer);
+            if is_root || dir == project_dir {
+                break;
+            }
+        }
+        Ok(layers
+            .into_iter()
+            .rev()
+            .fold(Self::default(), Self::overlay))
+    }
+
+    pub fn from_user_config() -> Result<Self> {
+        let path = user_config_dir().and_then(|dir| {
+            USER_CONFIG_FILES
+                .iter()
+                .map(|file| dir.join(file))
+                .find(|path| path.is_file())
+        });
+        match path {
+            Some(path) => Self::from_path(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Layers `other` on top of `self`. Modes with the same name are merged.
+    pub fn overlay(self, other: Self) -> Self {
+        let mut mode = self.mode.unwrap_or_default();
+        for (name, info) in other.mode.unwrap_or_default() {
+            let info = match mode.remove(&name) {
+                Some(lower) => lower.overlay(info),
+                None => info,
+            };
+            mode.insert(name, info);
+        }
+        let mut sources = self.sources;
+        sources.extend(other.sources);
+
+        Self {
+            base: self.base.overlay(other.base),
+            mode: (!mode.is_empty()).then_some(mode),
+            default_mode: other.default_mode.or(self.default_mode),
+            root: other.root.or(self.root),
+            include: other.include.or(self.include),
+            sources,
+        }
+    }
+
+    fn resolve_mode(
+        &self,
+        name: &str,
+        stack: &mut Vec<String>,
+    ) -> Result<Option<BuildInfo>> {
+        let Some(mode) = self.mode.as_ref().and_then(|x| x.get(name)) else {
+            return Ok(None);
+        };
+        if !mode.is_active() {
+            return Ok(Some(BuildInfo::default()));
+        }
+        if stack.iter().any(|x| x == name) {
+            stack.push(name.to_string());
+            return Err(Error::ModeCycle(stack.join(" -> ")));
+        }
+
+        stack.push(name.to_string());
+        let mut build_info = BuildInfo::default();
+        let parents: Vec<String> =
+            mode.extends.clone().unwrap_or_default().into();
+        for parent in &parents {
+            let parent_info = self
+                .resolve_mode(parent, stack)?
+                .ok_or_else(|| Error::UnknownMode(parent.clone()))?;
+            build_info = build_info.inherit(parent_info);
+        }
+        stack.pop();
+
+        let mode = mode.clone().apply_conditions();
+        Ok(Some(build_info.inherit(mode)))
+    }
+
+    pub fn build_info(&self, modes: &[String]) -> Result<BuildInfo> {
+        let mut build_info = self.base.clone().apply_conditions();
+        for mode in modes {
+            let mode_info = self.resolve_mode(mode, &mut vec![])?;
+            build_info = build_info.merge(mode_info);
+        }
+        Ok(build_info)
+    }
+
+    pub fn modes(&self) -> Vec<(&str, &BuildInfo)> {
+        let mut modes: Vec<_> = self
+            .mode
+            .iter()
+            .flatten()
+            .map(|(name, info)| (name.as_str(), info))
+            .collect();
+        modes.sort_by_key(|(name, _)| *name);
+        modes
     }
 }
 
@@ -125,27 +594,530 @@ impl BuildInfo {
         }
     }
 
-    fn merge_field<T, A>(a: Option<T>, b: Option<T>) -> Option<T>
+    /// Merges a list like field. `Append` adds `b` after `a`, `Prepend`
+    /// before it and `Replace` drops `a` if `b` is set. For maps the entries
+    /// that come last win.
+    fn merge_field<T, A>(
+        a: Option<T>,
+        b: Option<T>,
+        strategy: MergeStrategy,
+    ) -> Option<T>
     where
         T: Clone + Extend<A> + IntoIterator<Item = A>,
     {
-        if a.is_some() && b.is_some() {
-            let mut a = a.unwrap();
-            a.extend(b.unwrap());
-            Some(a)
-        } else {
-            a.or(b)
+        use MergeStrategy::*;
+        match (a, b, strategy) {
+            (Some(mut a), Some(b), Append) => {
+                a.extend(b);
+                Some(a)
+            }
+            (Some(a), Some(mut b), Prepend) => {
+                b.extend(a);
+                Some(b)
+            }
+            (a, b, _) => b.or(a),
         }
     }
 
+    /// Merges a scalar field. The first value wins, so `Append` keeps `a`
+    /// while `Prepend` and `Replace` prefer `b`.
+    fn merge_value<T>(
+        a: Option<T>,
+        b: Option<T>,
+        strategy: MergeStrategy,
+    ) -> Option<T> {
+        match strategy {
+            MergeStrategy::Append => a.or(b),
+            MergeStrategy::Prepend | MergeStrategy::Replace => b.or(a),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.when.as_ref().is_none_or(|x| x.matches())
+    }
+
+    /// Merges all `conditional` blocks whose `when` matches the host. Like
+    /// a layered file, single values of a block win.
+    pub fn apply_conditions(mut self) -> Self {
+        let conditional = self.conditional.take().unwrap_or_default();
+        for block in conditional.into_iter().filter(|x| x.is_active()) {
+            let block = block.apply_conditions();
+            self = self.overlay(Self {
+                when: None,
+                ..block
+            });
+        }
+        self
+    }
+
+    /// Expands variable references in all fields that end up in commands or
+    /// the environment of the build.
+    pub fn expand(self, vars: &Variables) -> Result<Self> {
+        let expand_hooks = |hooks: Option<Vec<StringOrArray>>| {
+            hooks
+                .map(|x| x.into_iter().map(|x| x.expand_script(vars)).collect())
+                .transpose()
+        };
+        let env = self
+            .env
+            .map(|env| {
+                env.into_iter()
+                    .map(|(k, v)| Ok((k, vars.expand(&v)?)))
+                    .collect::<Result<_>>()
+            })
+            .transpose()?;
+
+        Ok(Self {
+            container: self.container.map(|x| x.expand(vars)).transpose()?,
+            default: self.default.map(|x| x.expand(vars)).transpose()?,
+            configure: self
+                .configure
+                .map(|x| vars.expand_all(x))
+                .transpose()?,
+            env,
+            pre_configure: expand_hooks(self.pre_configure)?,
+            pre_build: expand_hooks(self.pre_build)?,
+            post_build: expand_hooks(self.post_build)?,
+            depends_on: self
+                .depends_on
+                .map(|x| vars.expand_all(x))
+                .transpose()?,
+            ..self
+        })
+    }
+
+    /// Merges a mode into `self`. Single values already set in `self` are
+    /// kept unless the mode asks for something else.
     pub fn merge(self, other: Option<Self>) -> Self {
-        let other = other.unwrap_or_default();
+        self.combine(other.unwrap_or_default(), Layering::Merge)
+    }
+
+    /// Layers a configuration file on top of `self`. In contrast to `merge`,
+    /// single values of `other` win and `extends` and `when` are kept, as
+    /// they are not resolved yet.
+    pub fn overlay(self, other: Self) -> Self {
+        self.combine(other, Layering::Overlay)
+    }
+
+    /// Merges a mode into the modes it extends. The strategies of each mode
+    /// are collected, but only apply when the resolved mode is merged into
+    /// the configuration below it, so a mode cannot drop values of its
+    /// siblings.
+    fn inherit(self, other: Self) -> Self {
+        self.combine(other, Layering::Inherit)
+    }
+
+    fn combine(self, other: Self, layering: Layering) -> Self {
+        let strategies = match layering {
+            Layering::Inherit => HashMap::new(),
+            _ => other.merge.clone().unwrap_or_default(),
+        };
+        let s = |field| strategies.get(field).copied().unwrap_or_default();
+        let v = |field| match strategies.get(field) {
+            Some(strategy) => *strategy,
+            None if layering == Layering::Merge => MergeStrategy::Append,
+            None => MergeStrategy::Replace,
+        };
+        let merge =
+            Self::merge_field(self.merge, other.merge, MergeStrategy::Append);
+        let (extends, when) = if layering == Layering::Overlay {
+            (other.extends.or(self.extends), other.when.or(self.when))
+        } else {
+            (None, None)
+        };
         Self {
-            container: self.container.or(other.container),
-            default: self.default.or(other.default),
-            configure: Self::merge_field(self.configure, other.configure),
-            build_system: self.build_system.or(other.build_system),
-            env: Self::merge_field(self.env, other.env),
+            description: Self::merge_value(
+                self.description,
+                other.description,
+                v("description"),
+            ),
+            extends,
+            merge,
+            when,
+            conditional: Self::merge_field(
+                self.conditional,
+                other.conditional,
+                MergeStrategy::Append,
+            ),
+            container: Self::merge_value(
+                self.container,
+                other.container,
+                v("container"),
+            ),
+            container_runtime: Self::merge_value(
+                self.container_runtime,
+                other.container_runtime,
+                v("container_runtime"),
+            ),
+            build_dir: Self::merge_value(
+                self.build_dir,
+                other.build_dir,
+                v("build_dir"),
+            ),
+            default: Self::merge_value(
+                self.default,
+                other.default,
+                v("default"),
+            ),
+            configure: Self::merge_field(
+                self.configure,
+                other.configure,
+                s("configure"),
+            ),
+            build_system: Self::merge_value(
+                self.build_system,
+                other.build_system,
+                v("build_system"),
+            ),
+            env: Self::merge_field(self.env, other.env, s("env")),
+            pre_configure: Self::merge_field(
+                self.pre_configure,
+                other.pre_configure,
+                s("pre_configure"),
+            ),
+            pre_build: Self::merge_field(
+                self.pre_build,
+                other.pre_build,
+                s("pre_build"),
+            ),
+            post_build: Self::merge_field(
+                self.post_build,
+                other.post_build,
+                s("post_build"),
+            ),
+            depends_on: Self::merge_field(
+                self.depends_on,
+                other.depends_on,
+                s("depends_on"),
+            ),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use MergeStrategy::*;
+
+    fn mk_info(yaml: &str) -> MkInfo {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    /// Creates an empty directory for a test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "mk-info-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(dir: &Path, file: &str, content: &str) {
+        let path = dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn configure(mk_info: &MkInfo, modes: &[&str]) -> Vec<String> {
+        let modes: Vec<_> = modes.iter().map(|x| x.to_string()).collect();
+        mk_info
+            .build_info(&modes)
+            .unwrap()
+            .configure
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn merge_field_strategies() {
+        let a = Some(vec![1, 2]);
+        let b = Some(vec![3]);
+        let merge = |s| BuildInfo::merge_field(a.clone(), b.clone(), s);
+        assert_eq!(merge(Append), Some(vec![1, 2, 3]));
+        assert_eq!(merge(Prepend), Some(vec![3, 1, 2]));
+        assert_eq!(merge(Replace), Some(vec![3]));
+        assert_eq!(BuildInfo::merge_field(a.clone(), None, Replace), a);
+        assert_eq!(BuildInfo::merge_field(None, b.clone(), Append), b);
+    }
+
+    #[test]
+    fn merge_value_strategies() {
+        assert_eq!(BuildInfo::merge_value(Some(1), Some(2), Append), Some(1));
+        assert_eq!(BuildInfo::merge_value(Some(1), Some(2), Prepend), Some(2));
+        assert_eq!(BuildInfo::merge_value(Some(1), Some(2), Replace), Some(2));
+        assert_eq!(BuildInfo::merge_value(None, Some(2), Append), Some(2));
+        assert_eq!(BuildInfo::merge_value(Some(1), None, Replace), Some(1));
+    }
+
+    #[test]
+    fn mode_strategies_apply_to_base() {
+        let info = mk_info(
+            r#"
+configure: [-Dbase]
+build_dir: build
+mode:
+  release:
+    merge: {configure: replace, build_dir: replace}
+    configure: [-Dbuildtype=release]
+    build_dir: build-release
+  debug:
+    merge: {configure: prepend}
+    configure: [-Dbuildtype=debug]
+    build_dir: build-debug
+"#,
+        );
+        assert_eq!(configure(&info, &["release"]), ["-Dbuildtype=release"]);
+        assert_eq!(
+            configure(&info, &["debug"]),
+            ["-Dbuildtype=debug", "-Dbase"]
+        );
+        let build_dir = |mode: &str| {
+            info.build_info(&[mode.to_string()])
+                .unwrap()
+                .build_dir
+                .unwrap()
+        };
+        assert_eq!(build_dir("release"), "build-release");
+        assert_eq!(build_dir("debug"), "build");
+    }
+
+    #[test]
+    fn extends_multiple_parents() {
+        let info = mk_info(
+            r#"
+configure: [-Dbase]
+mode:
+  opt:
+    configure: [-Doptimization=3]
+  release:
+    merge: {configure: replace}
+    configure: [-Dbuildtype=release]
+  both:
+    extends: [opt, release]
+    configure: [-Dstrip=true]
+"#,
+        );
+        assert_eq!(
+            configure(&info, &["both"]),
+            ["-Doptimization=3", "-Dbuildtype=release", "-Dstrip=true"]
+        );
+    }
+
+    #[test]
+    fn conditions_select_blocks_and_modes() {
+        let info = mk_info(&format!(
+            r#"
+configure: [-Dbase]
+build_dir: build
+conditional:
+  - when: {{os: {os}}}
+    configure: [-Dhost]
+    build_dir: build-host
+  - when: {{os: mk-test-os}}
+    configure: [-Dother]
+mode:
+  host: {{when: {{os: {os}}}, configure: [-Dmode]}}
+  other: {{when: {{os: mk-test-os}}, configure: [-Dmode]}}
+"#,
+            os = env::consts::OS
+        ));
+        let build_info = info.build_info(&[]).unwrap();
+        assert_eq!(build_info.configure.unwrap(), ["-Dbase", "-Dhost"]);
+        assert_eq!(build_info.build_dir.unwrap(), "build-host");
+        assert_eq!(configure(&info, &["host"]), ["-Dbase", "-Dhost", "-Dmode"]);
+        assert_eq!(configure(&info, &["other"]), ["-Dbase", "-Dhost"]);
+    }
+
+    #[test]
+    fn extends_cycle() {
+        let info = mk_info(
+            r#"
+mode:
+  a: {extends: b}
+  b: {extends: [c]}
+  c: {extends: a}
+"#,
+        );
+        let error = info.build_info(&["a".to_string()]).unwrap_err();
+        assert!(
+            matches!(&error, Error::ModeCycle(x) if x == "a -> b -> c -> a"),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn extends_unknown_mode() {
+        let info = mk_info("mode: {a: {extends: missing}}");
+        let error = info.build_info(&["a".to_string()]).unwrap_err();
+        assert!(
+            matches!(&error, Error::UnknownMode(x) if x == "missing"),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn overlay_and_merge_layering() {
+        let lower = mk_info("{configure: [-Dlower], build_dir: lower}").base;
+        let upper = mk_info("{configure: [-Dupper], build_dir: upper}").base;
+
+        let overlaid = lower.clone().overlay(upper.clone());
+        assert_eq!(overlaid.configure.unwrap(), ["-Dlower", "-Dupper"]);
+        assert_eq!(overlaid.build_dir.unwrap(), "upper");
+
+        let merged = lower.merge(Some(upper));
+        assert_eq!(merged.configure.unwrap(), ["-Dlower", "-Dupper"]);
+        assert_eq!(merged.build_dir.unwrap(), "lower");
+    }
+
+    #[test]
+    fn overlay_merges_modes_by_name() {
+        let lower = mk_info("{mode: {a: {configure: [-Da]}, b: {}}}");
+        let upper = mk_info("{mode: {a: {configure: [-Dupper]}}}");
+        let info = lower.overlay(upper);
+        assert_eq!(configure(&info, &["a"]), ["-Da", "-Dupper"]);
+        assert!(info.mode.unwrap().contains_key("b"));
+    }
+
+    #[test]
+    fn files_of_parent_directories_are_layered() {
+        let project = temp_dir("layered");
+        let work_dir = project.join("sub/deeper");
+        fs::create_dir_all(&work_dir).unwrap();
+        write(
+            &project,
+            ".Mk.yaml",
+            "{configure: [-Dproject], build_dir: a}",
+        );
+        write(
+            &project,
+            "sub/.Mk.yaml",
+            "{configure: [-Dsub], build_dir: b}",
+        );
+        write(&project, "sub/.Mk.local.yaml", "configure: [-Dlocal]");
+
+        let info = MkInfo::from_dirs(&project, &work_dir).unwrap();
+        let build_info = info.build_info(&[]).unwrap();
+        assert_eq!(
+            build_info.configure.unwrap(),
+            ["-Dproject", "-Dsub", "-Dlocal"]
+        );
+        assert_eq!(build_info.build_dir.unwrap(), "b");
+
+        fs::remove_dir_all(&project).unwrap();
+    }
+
+    #[test]
+    fn root_stops_the_search() {
+        let project = temp_dir("root");
+        let work_dir = project.join("sub");
+        write(&project, ".Mk.yaml", "configure: [-Dproject]");
+        write(&project, "sub/.Mk.yaml", "{root: true, configure: [-Dsub]}");
+
+        let info = MkInfo::from_dirs(&project, &work_dir).unwrap();
+        assert_eq!(configure(&info, &[]), ["-Dsub"]);
+        // Directories above the project are never searched.
+        let info = MkInfo::from_dirs(&work_dir, &work_dir).unwrap();
+        assert_eq!(configure(&info, &[]), ["-Dsub"]);
+        fs::remove_file(work_dir.join(".Mk.yaml")).unwrap();
+        let info = MkInfo::from_dirs(&work_dir, &work_dir).unwrap();
+        assert_eq!(configure(&info, &[]), Vec::<String>::new());
+
+        fs::remove_dir_all(&project).unwrap();
+    }
+
+    #[test]
+    fn nested_includes_are_relative_to_their_file() {
+        let dir = temp_dir("include");
+        write(
+            &dir,
+            ".Mk.yaml",
+            "{include: [shared/a.yaml], configure: [-Dmk]}",
+        );
+        write(&dir, "shared/a.yaml", "{include: b.yaml, configure: [-Da]}");
+        write(&dir, "shared/b.yaml", "{root: true, configure: [-Db]}");
+
+        let info = MkInfo::from_path(&dir.join(".Mk.yaml")).unwrap();
+        assert_eq!(configure(&info, &[]), ["-Db", "-Da", "-Dmk"]);
+        assert_eq!(info.root, None);
+        assert_eq!(info.sources.len(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = temp_dir("include-cycle");
+        write(&dir, ".Mk.yaml", "include: a.yaml");
+        write(&dir, "a.yaml", "include: b.yaml");
+        write(&dir, "b.yaml", "include: a.yaml");
+
+        let mut error = MkInfo::from_path(&dir.join(".Mk.yaml")).unwrap_err();
+        while let Error::Include(_, inner) = error {
+            error = *inner;
+        }
+        let a = dir.join("a.yaml").canonicalize().unwrap();
+        assert!(
+            matches!(&error, Error::IncludeCycle(x) if *x == a),
+            "{:?}",
+            error
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_toml_and_json() {
+        let dir = temp_dir("formats");
+        write(&dir, ".Mk.toml", "configure = [\"-Dtoml\"]\n[mode.a]\n");
+        let info = MkInfo::from_root_path(&dir).unwrap();
+        assert_eq!(configure(&info, &["a"]), ["-Dtoml"]);
+
+        write(&dir, ".Mk.json", r#"{"configure": ["-Djson"]}"#);
+        let error = MkInfo::find_root_path(&dir).unwrap_err();
+        assert!(matches!(error, Error::ConflictingMk(..)), "{:?}", error);
+        fs::remove_file(dir.join(".Mk.toml")).unwrap();
+        let info = MkInfo::from_root_path(&dir).unwrap();
+        assert_eq!(configure(&info, &[]), ["-Djson"]);
+
+        write(&dir, ".Mk.json", r#"{"configur": []}"#);
+        let error = MkInfo::from_root_path(&dir).unwrap_err();
+        assert!(error.to_string().contains("did you mean `configure`?"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_cargo_metadata() {
+        let dir = temp_dir("cargo");
+        let package = "[package]\nname = \"app\"\n[dependencies]\n";
+        write(&dir, "Cargo.toml", package);
+        assert_eq!(MkInfo::find_root_path(&dir).unwrap(), None);
+
+        let metadata = "[package.metadata.other]\nconfigure = 1\n\
+            [package.metadata.mk]\nconfigure = [\"-Dcargo\"]\n";
+        write(&dir, "Cargo.toml", &format!("{}{}", package, metadata));
+        let path = MkInfo::find_root_path(&dir).unwrap().unwrap();
+        assert_eq!(path, dir.join("Cargo.toml"));
+        let info = MkInfo::from_path(&path).unwrap();
+        assert_eq!(configure(&info, &[]), ["-Dcargo"]);
+
+        write(
+            &dir,
+            "Cargo.toml",
+            "[package.metadata.mk]\nbuild-dir = \"x\"\n",
+        );
+        let error = MkInfo::from_root_path(&dir).unwrap_err();
+        assert!(error.to_string().contains("did you mean `build_dir`?"));
+
+        write(&dir, ".Mk.yaml", "{}");
+        write(&dir, "Cargo.toml", &format!("{}{}", package, metadata));
+        let error = MkInfo::find_root_path(&dir).unwrap_err();
+        assert!(matches!(error, Error::ConflictingMk(..)), "{:?}", error);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
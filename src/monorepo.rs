@@ -0,0 +1,151 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    build_system::{RootIdentificationResult, BUILD_SYSTEMS},
+    project::Project,
+    Error, Result,
+};
+
+/// Directories that never contain subprojects.
+const SKIPPED_DIRS: &[&str] = &["node_modules", "CVS", "_darcs"];
+
+fn is_skipped(name: &str) -> bool {
+    name.starts_with('.') || SKIPPED_DIRS.contains(&name)
+}
+
+fn has_build_files(path: &Path) -> Result<bool> {
+    for build_system in BUILD_SYSTEMS {
+        if build_system.is_project_root(path)?
+            != RootIdentificationResult::NotRoot
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Finds all directories below `root` that one of the build systems can
+/// build. The search does not descend into a project once it is found, so
+/// subdirectories of a project are not reported separately. Hidden
+/// directories are skipped.
+pub fn find_projects(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut projects = vec![];
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        if has_build_files(&dir)? {
+            projects.push(dir);
+            continue;
+        }
+        let entries =
+            fs::read_dir(&dir).map_err(|e| Error::Io(dir.clone(), e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| Error::Io(dir.clone(), e))?;
+            let is_dir = entry.file_type().is_ok_and(|x| x.is_dir());
+            let name = entry.file_name();
+            if is_dir && !is_skipped(&name.to_string_lossy()) {
+                pending.push(entry.path());
+            }
+        }
+    }
+    projects.sort();
+    Ok(projects)
+}
+
+/// The build directories of `projects`. `find_projects` does not know
+/// them, so it reports the build files generated in a build directory
+/// outside of its project as another project.
+pub fn build_dirs(projects: &[Project]) -> Vec<PathBuf> {
+    projects
+        .iter()
+        .filter_map(|x| {
+            let dir = x.build_dir.canonicalize().ok()?;
+            // A build directory containing its own project would hide it.
+            (!x.project_dir.starts_with(&dir)).then_some(dir)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opts;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "mk-monorepo-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn write(dir: &Path, file: &str, content: &str) {
+        let path = dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn finds_projects() {
+        let root = temp_dir("find");
+        write(&root, "b/Makefile", "");
+        write(&root, "a/CMakeLists.txt", "");
+        write(&root, "a/sub/Makefile", "");
+        write(&root, "group/c/meson.build", "");
+        write(&root, ".hidden/Makefile", "");
+        write(&root, ".git/Makefile", "");
+        write(&root, "node_modules/x/Makefile", "");
+        write(&root, "CVS/Makefile", "");
+        write(&root, "docs/index.md", "");
+
+        let projects = find_projects(&root).unwrap();
+        let expected: Vec<_> =
+            ["a", "b", "group/c"].iter().map(|x| root.join(x)).collect();
+        assert_eq!(projects, expected);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn root_project_hides_subprojects() {
+        let root = temp_dir("root");
+        write(&root, "Makefile", "");
+        write(&root, "lib/Makefile", "");
+        assert_eq!(find_projects(&root).unwrap(), vec![root.clone()]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn build_dirs_of_projects() {
+        let root = temp_dir("build-dirs");
+        fs::create_dir(root.join(".git")).unwrap();
+        write(&root, "app/CMakeLists.txt", "");
+        write(&root, "app/.Mk.yaml", "build_dir: ../build-app");
+        // The Makefile CMake generated in the build directory.
+        write(&root, "build-app/Makefile", "");
+        write(&root, "lib/CMakeLists.txt", "");
+        write(&root, "lib/.Mk.yaml", "build_dir: ..");
+
+        let found = find_projects(&root).unwrap();
+        assert_eq!(found.len(), 3);
+        let projects: Vec<_> = ["app", "lib"]
+            .iter()
+            .map(|x| {
+                let opts = Opts {
+                    cwd: root.join(x),
+                    ..Default::default()
+                };
+                Project::from_opts(&opts).unwrap()
+            })
+            .collect();
+        // The build directory of lib contains lib itself, so it is kept.
+        assert_eq!(build_dirs(&projects), [root.join("build-app")]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
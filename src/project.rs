@@ -2,76 +2,204 @@ use crate::{
     build_system::{
         build_system_from_str, RootIdentificationResult, BUILD_SYSTEMS,
     },
+    condition::find_in_path,
+    diagnostics::{resolve_path, Diagnostic, Parser},
     editor_config::EditorConfig,
-    mk_info::MkInfo,
+    events::{self, Event},
+    history,
+    mk_info::{BuildInfo, MkInfo, StringOrArray},
+    validate::suggest,
+    variables::Variables,
     Error, Result,
 };
 use std::{
+    borrow::Cow,
     collections::HashMap,
-    env, io, iter,
+    env,
+    fs::{self, File},
+    io::{self, Read, Write},
+    iter,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
     path::{Path, PathBuf},
-    process::{Command, ExitStatus},
-    time::SystemTime,
+    process::{Child, Command, ExitStatus, Stdio},
+    ptr,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
 };
 
 use crate::{build_system::BuildSystem, Opts};
 
+/// How often `pass_output` checks whether the command exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long output is read after the command exited.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(200);
+
+const QUICKFIX_FILE: &str = "mk-quickfix.txt";
+const DIAGNOSTICS_FILE: &str = "mk-diagnostics.json";
+
 const VCS_ROOT_DIRS: &[&str] = &[".git", ".hg", "_darcs", ".bzr"];
 const VCS_TIL_ROOT_DIRS: &[&str] = &[".svn", "CVS"];
 
+/// A stream of output of a command and where mk passes it on.
+struct OutputStream {
+    input: File,
+    output: Box<dyn Write>,
+    /// The incomplete last line.
+    line: Vec<u8>,
+    open: bool,
+}
+
+impl OutputStream {
+    fn new(input: File, output: Box<dyn Write>) -> Self {
+        Self {
+            input,
+            output,
+            line: vec![],
+            open: true,
+        }
+    }
+}
+
+/// Opens a pseudo terminal with the size of the terminal mk runs in.
+fn open_pty() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut master = -1;
+    let mut slave = -1;
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let size = if unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) } == 0 {
+        &size as *const libc::winsize
+    } else {
+        ptr::null()
+    };
+    let result = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null(),
+            size,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fds =
+        unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    // Leave the translation of line endings to the terminal mk writes to.
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(slave, &mut termios) } == 0 {
+        termios.c_oflag &= !libc::ONLCR;
+        unsafe { libc::tcsetattr(slave, libc::TCSANOW, &termios) };
+    }
+    for fd in [master, slave] {
+        // Children only get the terminal as their stdout and stderr.
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(fds)
+}
+
 fn has_root_editor_config(path: &Path) -> Result<bool> {
-    path.join(".editorconfig")
-        .exists()
-        .then(|| EditorConfig::from_file(path).map(|x| x.root))
-        .unwrap_or(Ok(false))
+    let path = path.join(".editorconfig");
+    if path.exists() {
+        EditorConfig::from_file(&path).map(|x| x.root)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Prints a step of the project root detection if `-mv` is given.
+macro_rules! trace {
+    ($verbose:expr, $($arg:tt)*) => {
+        if $verbose {
+            eprintln!("mk: {}", format_args!($($arg)*));
+        }
+    };
 }
 
-fn is_project_root(path: &Path) -> Result<RootIdentificationResult> {
+fn is_project_root(
+    path: &Path,
+    verbose: bool,
+) -> Result<RootIdentificationResult> {
     use RootIdentificationResult::*;
 
-    Ok(if VCS_ROOT_DIRS.iter().any(|x| path.join(x).is_dir()) {
-        IsRoot
-    } else if VCS_TIL_ROOT_DIRS.iter().any(|x| path.join(x).is_dir()) {
-        MaybeRoot
+    if let Some(dir) = VCS_ROOT_DIRS.iter().find(|x| path.join(x).is_dir()) {
+        trace!(verbose, "  {} found: {:?}", dir, IsRoot);
+        Ok(IsRoot)
+    } else if let Some(dir) =
+        VCS_TIL_ROOT_DIRS.iter().find(|x| path.join(x).is_dir())
+    {
+        trace!(verbose, "  {} found: {:?}", dir, MaybeRoot);
+        Ok(MaybeRoot)
     } else if has_root_editor_config(path)? {
-        IsRoot
+        trace!(verbose, "  .editorconfig with root = true: {:?}", IsRoot);
+        Ok(IsRoot)
     } else {
-        NotRoot
-    })
+        trace!(verbose, "  no version control or root .editorconfig");
+        Ok(NotRoot)
+    }
 }
 
 pub struct RootInfo {
     build_system: Option<&'static dyn BuildSystem>,
-    project_dir: PathBuf,
+    pub project_dir: PathBuf,
+    reason: String,
 }
 
 impl RootInfo {
     fn new(
         build_system: Option<&'static dyn BuildSystem>,
         project_dir: &Path,
+        reason: String,
     ) -> Self {
         Self {
             build_system,
             project_dir: project_dir.to_path_buf(),
+            reason,
         }
     }
 }
 
-pub fn find_root(path: &Path) -> Result<RootInfo> {
+pub fn find_root(path: &Path, verbose: bool) -> Result<RootInfo> {
     use RootIdentificationResult::*;
     let mut maybe_build_system = None;
     let mut maybe_root = false;
+    let mut stop_reason = "stopped at the filesystem root";
 
     for candidate in path.ancestors() {
+        trace!(verbose, "visiting {}", candidate.display());
         let last_maybe_build_system = maybe_build_system.take();
         for build_system in BUILD_SYSTEMS {
-            match build_system.is_project_root(candidate)? {
+            let result = build_system.is_project_root(candidate)?;
+            trace!(verbose, "  {}: {:?}", build_system.name(), result);
+            match result {
                 IsRoot => {
-                    return Ok(RootInfo::new(Some(*build_system), candidate))
+                    let reason = format!(
+                        "{} identified the project root",
+                        build_system.name()
+                    );
+                    trace!(
+                        verbose,
+                        "project root is {}: {}",
+                        candidate.display(),
+                        reason
+                    );
+                    return Ok(RootInfo::new(
+                        Some(*build_system),
+                        candidate,
+                        reason,
+                    ));
                 }
                 MaybeRoot => {
-                    maybe_build_system =
-                        Some(RootInfo::new(Some(*build_system), candidate));
+                    let reason = format!(
+                        "outermost directory with {} files",
+                        build_system.name()
+                    );
+                    maybe_build_system = Some(RootInfo::new(
+                        Some(*build_system),
+                        candidate,
+                        reason,
+                    ));
                 }
                 NotRoot => {}
             }
@@ -79,28 +207,50 @@ pub fn find_root(path: &Path) -> Result<RootInfo> {
 
         if maybe_build_system.is_none() && last_maybe_build_system.is_some() {
             maybe_build_system = last_maybe_build_system;
+            stop_reason = "stopped as the parent directory has none";
             break;
         }
-        match is_project_root(candidate)? {
-            IsRoot => break,
+        match is_project_root(candidate, verbose)? {
+            IsRoot => {
+                stop_reason =
+                    "stopped at a version control or .editorconfig root";
+                break;
+            }
             MaybeRoot => {
                 maybe_root = true;
                 if maybe_build_system.is_none() {
-                    maybe_build_system = Some(RootInfo::new(None, candidate))
+                    let reason = "outermost version control directory".into();
+                    maybe_build_system =
+                        Some(RootInfo::new(None, candidate, reason))
                 }
             }
             NotRoot => {
                 if maybe_root {
+                    stop_reason =
+                        "stopped after leaving the version control checkout";
                     break;
                 }
             }
         }
     }
 
-    maybe_build_system.ok_or(Error::NoProjectRootFound)
+    trace!(verbose, "{}", stop_reason);
+    let mut root_info = maybe_build_system.ok_or(Error::NoProjectRootFound)?;
+    root_info.reason = format!("{}, {}", root_info.reason, stop_reason);
+    trace!(
+        verbose,
+        "project root is {}: {}",
+        root_info.project_dir.display(),
+        root_info.reason
+    );
+    Ok(root_info)
 }
 
 pub struct Project {
+    pub mk_info: MkInfo,
+    pub build_info: BuildInfo,
+    pub root_reason: String,
+    pub modes: Vec<String>,
     pub container_image: Option<String>,
     pub container_args: Option<Vec<String>>,
     pub mk_info_path: Option<PathBuf>,
@@ -110,8 +260,73 @@ pub struct Project {
     pub configure_args: Vec<String>,
     pub args: Vec<String>,
     pub env: HashMap<String, String>,
-    pub build_system: &'static (dyn BuildSystem),
+    pub build_system: &'static dyn BuildSystem,
     pub container: bool,
+    pub dry_run: bool,
+    pub container_runtime: Option<String>,
+    pub pre_configure: Vec<Vec<String>>,
+    pub pre_build: Vec<Vec<String>>,
+    pub post_build: Vec<Vec<String>>,
+    pub depends_on: Vec<PathBuf>,
+    pub jobserver: Option<jobserver::Client>,
+    /// Prefixes each line of output if set, so that the output of
+    /// concurrent builds can be told apart.
+    pub output_prefix: Option<String>,
+    pub diagnostics: Mutex<Vec<Diagnostic>>,
+}
+
+fn hook_commands(hooks: Option<Vec<StringOrArray>>) -> Vec<Vec<String>> {
+    hooks
+        .unwrap_or_default()
+        .into_iter()
+        .map(|hook| match hook {
+            StringOrArray::String(script) => {
+                vec!["sh".to_string(), "-c".to_string(), script]
+            }
+            StringOrArray::Array(command) => command,
+        })
+        .collect()
+}
+
+/// Quotes `arg` for POSIX shells, leaving it alone if it is safe as is.
+pub fn shell_quote(arg: &str) -> Cow<'_, str> {
+    let is_safe =
+        |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        Cow::Borrowed(arg)
+    } else {
+        Cow::Owned(format!("'{}'", arg.replace('\'', "'\\''")))
+    }
+}
+
+fn remove_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|_, x| !x.is_null());
+            map.values_mut().for_each(remove_nulls);
+        }
+        serde_json::Value::Array(array) => {
+            array.iter_mut().for_each(remove_nulls);
+        }
+        _ => {}
+    }
+}
+
+fn warn_unknown_modes(mk_info: &MkInfo, mode: &str) {
+    let known: Vec<&str> =
+        mk_info.modes().into_iter().map(|(name, _)| name).collect();
+    for mode in mode.split_whitespace() {
+        if known.contains(&mode) {
+            continue;
+        }
+        match suggest(mode, known.iter().copied()) {
+            Some(x) => eprintln!(
+                "Warning: Unknown mode: {}, did you mean {}?",
+                mode, x
+            ),
+            None => eprintln!("Warning: Unknown mode: {}", mode),
+        }
+    }
 }
 
 impl Project {
@@ -123,56 +338,89 @@ impl Project {
         let RootInfo {
             build_system,
             project_dir,
-        } = find_root(&work_dir)?;
+            reason: root_reason,
+        } = find_root(&work_dir, opts.verbose)?;
 
-        let mode = if let Ok(mode) = env::var("MKMODE") {
-            mode
-        } else {
-            "default".to_string()
-        };
-        let mk_info_path = if let Ok(mk_info) = env::var("MKINFO") {
-            Some(PathBuf::from(mk_info))
+        let (mk_info_path, mk_info) = if let Ok(mk_info) = env::var("MKINFO") {
+            let mk_info_path = PathBuf::from(mk_info);
+            let mut mk_info = MkInfo::from_path(&mk_info_path)?;
+            if let Some(local_path) = MkInfo::find_local_path(&project_dir)? {
+                mk_info = mk_info.overlay(MkInfo::from_path(&local_path)?);
+            }
+            (Some(mk_info_path), mk_info)
         } else {
-            MkInfo::find_root_path(&project_dir)?
+            let mk_info_path = MkInfo::find_root_path(&project_dir)?;
+            (mk_info_path, MkInfo::from_dirs(&project_dir, &work_dir)?)
         };
+        let mk_info = MkInfo::from_user_config()?.overlay(mk_info);
 
-        let mk_info = if let Some(mk_info_path) = &mk_info_path {
-            MkInfo::from_path(mk_info_path)?
+        let mode = if !opts.modes.is_empty() {
+            Some(opts.modes.join(" "))
+        } else if let Ok(mode) = env::var("MKMODE") {
+            Some(mode)
         } else {
-            MkInfo::default()
+            mk_info.default_mode.clone()
         };
-
-        let mut build_info = mk_info.base;
-        if let Some(mode_info) = mk_info.mode {
-            for mode in mode.split_whitespace() {
-                build_info = build_info.merge(mode_info.get(mode).cloned());
-            }
+        if let Some(mode) = &mode {
+            warn_unknown_modes(&mk_info, mode);
         }
+        let modes: Vec<String> = mode
+            .as_deref()
+            .unwrap_or("default")
+            .split_whitespace()
+            .map(|x| x.to_string())
+            .collect();
 
-        let configure_args = build_info.configure.clone().unwrap_or_default();
-        let build_dir = opts.build_dir.clone().unwrap_or("build".into());
+        let mut variables = Variables::new(&project_dir, &modes);
+        let build_info = mk_info.build_info(&modes)?;
+        let build_dir = match (&opts.build_dir, &build_info.build_dir) {
+            (Some(build_dir), _) => build_dir.clone(),
+            (None, Some(build_dir)) => variables.expand(build_dir)?.into(),
+            (None, None) => "build".into(),
+        };
         let build_dir = project_dir.join(build_dir);
+        variables.set_build_dir(&build_dir);
+        let build_info = build_info.expand(&variables)?;
+
+        let configure_args = build_info.configure.clone().unwrap_or_default();
         let container = opts.container;
+        let dry_run = opts.dry_run;
+        let container_runtime = build_info.container_runtime.clone();
         let container_image = build_info.image().map(|x| x.to_string());
         let container_args = build_info.container_args().map(|x| x.to_vec());
         let args = if opts.args.is_empty() {
-            build_info.default.unwrap_or_default().into()
+            build_info.default.clone().unwrap_or_default().into()
         } else {
             opts.args.clone()
         };
 
-        let build_system =
-            if let Some(build_system) = build_info.build_system {
-                build_system_from_str(&build_system)
-            } else {
-                build_system
-            }
-            .ok_or(Error::NoBuildSystemFound)?;
+        let build_system = if let Some(build_system) = &build_info.build_system
+        {
+            build_system_from_str(build_system)
+        } else {
+            build_system
+        }
+        .ok_or(Error::NoBuildSystemFound)?;
 
-        let env = build_info.env.unwrap_or_default();
+        let env = build_info.env.clone().unwrap_or_default();
+        let pre_configure = hook_commands(build_info.pre_configure.clone());
+        let pre_build = hook_commands(build_info.pre_build.clone());
+        let post_build = hook_commands(build_info.post_build.clone());
+        let depends_on = build_info
+            .depends_on
+            .iter()
+            .flatten()
+            .map(|x| project_dir.join(x))
+            .collect();
 
         Ok(Self {
+            mk_info,
+            build_info,
+            root_reason,
+            modes,
             container,
+            dry_run,
+            container_runtime,
             container_image,
             container_args,
             mk_info_path,
@@ -183,10 +431,60 @@ impl Project {
             configure_args,
             args,
             env,
+            pre_configure,
+            pre_build,
+            post_build,
+            depends_on,
+            jobserver: None,
+            output_prefix: None,
+            diagnostics: Mutex::new(vec![]),
         })
     }
 
+    /// Whether `clean` may remove the build directory. A directory outside
+    /// the project is only removed if it was configured as a build
+    /// directory, so a Mk file cannot wipe an arbitrary directory.
+    fn may_clean(&self) -> Result<bool> {
+        let build_dir = match self.build_dir.canonicalize() {
+            Ok(build_dir) => build_dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(Error::Io(self.build_dir.clone(), e)),
+        };
+        if self.project_dir.starts_with(&build_dir) {
+            return Ok(false);
+        }
+        if build_dir.starts_with(&self.project_dir) {
+            return Ok(true);
+        }
+        // The diagnostics of a failed configure step are all that is left.
+        let entries = fs::read_dir(&build_dir)
+            .map_err(|e| Error::Io(build_dir.clone(), e))?;
+        let mut names = entries.map(|x| x.map(|x| x.file_name()));
+        if names.all(|x| {
+            x.is_ok_and(|x| x == QUICKFIX_FILE || x == DIAGNOSTICS_FILE)
+        }) {
+            return Ok(true);
+        }
+        let marker = self.build_system.configure_marker(self)?;
+        Ok(marker.is_some_and(|x| x.exists()))
+    }
+
     pub fn clean(&self) -> Result<()> {
+        // Nothing to clean, and an empty directory outside the project may
+        // well be a fresh one the user created for the build.
+        let is_empty = std::fs::read_dir(&self.build_dir)
+            .is_ok_and(|mut x| x.next().is_none());
+        if is_empty {
+            return Ok(());
+        }
+        if !self.may_clean()? {
+            return Err(Error::UnsafeBuildDir(self.build_dir.clone()));
+        }
+        if self.dry_run {
+            let build_dir = self.build_dir.to_string_lossy();
+            println!("rm -rf {}", shell_quote(&build_dir));
+            return Ok(());
+        }
         match std::fs::remove_dir_all(&self.build_dir) {
             Ok(()) => Ok(()),
             Err(e) => {
@@ -203,24 +501,344 @@ impl Project {
         if let Ok(runtime) = std::env::var("CONTAINER_RUNTIME") {
             return Ok(PathBuf::from(runtime));
         }
-        let runtimes = ["podman", "docker"];
-        let Ok(path_var) = std::env::var("PATH") else {
-            return Err(Error::NoContainerRuntimeFound);
+        if let Some(runtime) = &self.container_runtime {
+            return find_in_path(runtime).ok_or(Error::NoContainerRuntimeFound);
+        }
+        ["podman", "docker"]
+            .iter()
+            .find_map(|x| find_in_path(x))
+            .ok_or(Error::NoContainerRuntimeFound)
+    }
+
+    pub fn run(&self, command: &[String]) -> Result<ExitStatus> {
+        self.run_with_env(command, &HashMap::new())
+    }
+
+    pub fn run_with_env(
+        &self,
+        command: &[String],
+        extra_env: &HashMap<String, String>,
+    ) -> Result<ExitStatus> {
+        self.run_command(command, extra_env, false)
+    }
+
+    /// Runs the configure or build command, collecting the diagnostics in
+    /// its output.
+    fn run_step(&self, command: &[String]) -> Result<ExitStatus> {
+        self.run_command(command, &HashMap::new(), true)
+    }
+
+    fn run_command(
+        &self,
+        command: &[String],
+        extra_env: &HashMap<String, String>,
+        collect: bool,
+    ) -> Result<ExitStatus> {
+        let mut env = self.env.clone();
+        env.extend(extra_env.clone());
+        let command = self.command_line(command, &env)?;
+
+        if self.dry_run {
+            self.print_command(&command, &env);
+            return Ok(ExitStatus::default());
+        }
+
+        let mut cmd = Command::new(&command[0]);
+        cmd.args(command.iter().skip(1))
+            .envs(&env)
+            .current_dir(&self.work_dir);
+        // The jobserver pipes do not reach into a container.
+        if let Some(jobserver) =
+            self.jobserver.as_ref().filter(|_| !self.container)
+        {
+            jobserver.configure_make(&mut cmd);
+        }
+        let status = if collect || self.output_prefix.is_some() {
+            self.run_captured(cmd, collect)
+        } else {
+            cmd.status()
         };
+        status.map_err(|e| Error::Command(command[0].clone(), e))
+    }
+
+    /// Runs `cmd`, passing its output through while collecting diagnostics
+    /// if `collect` is set.
+    fn run_captured(
+        &self,
+        mut cmd: Command,
+        collect: bool,
+    ) -> io::Result<ExitStatus> {
+        let mut parser = collect.then(Parser::default);
+        if self.output_prefix.is_none() && unsafe { libc::isatty(1) } != 0 {
+            return self.run_in_pty(cmd, parser.as_mut());
+        }
+
+        let mut child =
+            cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let stdout = File::from(OwnedFd::from(child.stdout.take().unwrap()));
+        let stderr = File::from(OwnedFd::from(child.stderr.take().unwrap()));
+        // Both streams share the parser, as make reports the directories on
+        // stdout and compilers report diagnostics on stderr. The lines of the
+        // two pipes are parsed in the order they can be read, which is not
+        // necessarily the order they were written in, so a diagnostic can
+        // end up in the wrong directory. Only the pty keeps the order.
+        let streams = vec![
+            OutputStream::new(stdout, Box::new(io::stdout())),
+            OutputStream::new(stderr, Box::new(io::stderr())),
+        ];
+        self.pass_output(&mut child, streams, parser.as_mut())
+    }
+
+    /// Runs `cmd` on a pseudo terminal, so it keeps its colors and progress
+    /// output while the diagnostics are collected.
+    fn run_in_pty(
+        &self,
+        mut cmd: Command,
+        parser: Option<&mut Parser>,
+    ) -> io::Result<ExitStatus> {
+        let (master, slave) = open_pty()?;
+        cmd.stdout(slave.try_clone()?).stderr(slave);
+        let mut child = cmd.spawn()?;
+        // Close our end of the terminal, so reading stops once the command
+        // and its children are done.
+        drop(cmd);
+
+        let streams = vec![OutputStream::new(
+            File::from(master),
+            Box::new(io::stdout()),
+        )];
+        self.pass_output(&mut child, streams, parser)
+    }
 
-        for path in path_var.split(':') {
-            for rt in &runtimes {
-                let path = Path::new(path).join(rt);
-                if path.exists() {
-                    return Ok(path);
+    /// Passes the output of `child` on until it exited and its output is
+    /// drained. Processes the command started in the background, like
+    /// sccache or gradle daemons, keep the streams open, so reading stops
+    /// `DRAIN_TIMEOUT` after the command exited instead of at the end of
+    /// the streams.
+    fn pass_output(
+        &self,
+        child: &mut Child,
+        mut streams: Vec<OutputStream>,
+        mut parser: Option<&mut Parser>,
+    ) -> io::Result<ExitStatus> {
+        let mut status = None;
+        let mut deadline: Option<Instant> = None;
+        let mut buf = [0; 4096];
+        while streams.iter().any(|x| x.open) {
+            let timeout = match deadline {
+                Some(deadline) => {
+                    deadline.saturating_duration_since(Instant::now())
                 }
+                None => POLL_INTERVAL,
+            };
+            // Closed streams get a negative fd, which poll ignores.
+            let mut fds: Vec<_> = streams
+                .iter()
+                .map(|x| libc::pollfd {
+                    fd: if x.open { x.input.as_raw_fd() } else { -1 },
+                    events: libc::POLLIN,
+                    revents: 0,
+                })
+                .collect();
+            let result = unsafe {
+                libc::poll(
+                    fds.as_mut_ptr(),
+                    fds.len() as libc::nfds_t,
+                    timeout.as_millis() as libc::c_int,
+                )
+            };
+            if result < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+            for (stream, fd) in streams.iter_mut().zip(&fds) {
+                if fd.revents == 0 {
+                    continue;
+                }
+                match stream.input.read(&mut buf) {
+                    Ok(0) => stream.open = false,
+                    Ok(n) => {
+                        self.pass(stream, &buf[..n], parser.as_deref_mut())
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    // Linux reports EIO once the other end of a pty is
+                    // closed.
+                    Err(_) => stream.open = false,
+                }
+            }
+
+            if status.is_none() {
+                status = child.try_wait()?;
+                if status.is_some() {
+                    deadline = Some(Instant::now() + DRAIN_TIMEOUT);
+                }
+            }
+            if deadline.is_some_and(|x| Instant::now() >= x) {
+                break;
+            }
+        }
+
+        for stream in &mut streams {
+            if !stream.line.is_empty() {
+                self.end_line(stream, parser.as_deref_mut());
             }
         }
-        Err(Error::NoContainerRuntimeFound)
+        match status {
+            Some(status) => Ok(status),
+            None => child.wait(),
+        }
     }
 
-    pub fn run(&self, command: &[String]) -> Result<ExitStatus> {
-        let command = if self.container {
+    /// Passes a chunk of output on. Output of concurrent builds is written
+    /// line by line with the prefix, so their lines don't mix, everything
+    /// else right away, which keeps progress output working.
+    fn pass(
+        &self,
+        stream: &mut OutputStream,
+        data: &[u8],
+        mut parser: Option<&mut Parser>,
+    ) {
+        if self.output_prefix.is_none() {
+            let _ = stream.output.write_all(data);
+            let _ = stream.output.flush();
+        }
+        for byte in data {
+            if *byte == b'\n' {
+                self.end_line(stream, parser.as_deref_mut());
+            } else {
+                stream.line.push(*byte);
+            }
+        }
+    }
+
+    fn end_line(&self, stream: &mut OutputStream, parser: Option<&mut Parser>) {
+        self.collect(&stream.line, parser);
+        if let Some(prefix) = &self.output_prefix {
+            let mut buf = format!("[{}] ", prefix).into_bytes();
+            buf.extend(&stream.line);
+            buf.push(b'\n');
+            let _ = stream.output.write_all(&buf);
+        }
+        stream.line.clear();
+    }
+
+    /// Looks for a diagnostic in a line of output.
+    fn collect(&self, line: &[u8], parser: Option<&mut Parser>) {
+        let Some(parser) = parser else {
+            return;
+        };
+        let text = String::from_utf8_lossy(line);
+        // Terminals end lines with `\r\n`, and progress output overwrites
+        // the line after a `\r`.
+        let text = text.trim_end_matches('\r');
+        let text = text.rsplit('\r').next().unwrap_or(text);
+        let Some(diagnostic) = parser.parse_line(text) else {
+            return;
+        };
+        if events::is_enabled() {
+            let resolved = self.resolve_diagnostic(diagnostic.clone());
+            events::emit(Event::Diagnostic {
+                project_dir: &self.project_dir,
+                diagnostic: &resolved,
+            });
+        }
+        self.diagnostics.lock().unwrap().push(diagnostic);
+    }
+
+    pub fn clear_diagnostics(&self) {
+        self.diagnostics.lock().unwrap().clear();
+    }
+
+    /// The diagnostics of the last build, with paths relative to the working
+    /// directory.
+    pub fn resolved_diagnostics(&self) -> Vec<Diagnostic> {
+        let diagnostics = self.diagnostics.lock().unwrap().clone();
+        diagnostics
+            .into_iter()
+            .map(|x| self.resolve_diagnostic(x))
+            .collect()
+    }
+
+    fn resolve_diagnostic(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        let dirs = [&*self.build_dir, &self.project_dir, &self.work_dir];
+        diagnostic.file = resolve_path(&diagnostic.file, &dirs, &self.work_dir);
+        diagnostic
+    }
+
+    /// Where the diagnostics are written: the output directory of the build
+    /// or, if it has none, a directory named after the project in the state
+    /// directory.
+    pub fn diagnostics_dir(&self) -> Option<PathBuf> {
+        self.build_system.output_dir(self).or_else(|| {
+            let name = self.project_dir.to_string_lossy().replace('/', "%");
+            history::state_dir().map(|x| x.join("diagnostics").join(name))
+        })
+    }
+
+    /// Writes the diagnostics of the last build as a quickfix file for vim
+    /// and as JSON. Nothing is written if there is nothing to report and
+    /// there are no files of an earlier build to update.
+    pub fn write_diagnostics(&self) -> Result<()> {
+        let Some(dir) = self.diagnostics_dir() else {
+            return Ok(());
+        };
+        let quickfix_path = dir.join(QUICKFIX_FILE);
+        let json_path = dir.join(DIAGNOSTICS_FILE);
+        let diagnostics = self.resolved_diagnostics();
+        if self.dry_run || diagnostics.is_empty() && !quickfix_path.exists() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&dir).map_err(|e| Error::Io(dir.clone(), e))?;
+        let quickfix: String =
+            diagnostics.iter().map(|x| format!("{}\n", x)).collect();
+        fs::write(&quickfix_path, quickfix)
+            .map_err(|e| Error::Io(quickfix_path, e))?;
+        let json = serde_json::to_string_pretty(&diagnostics).unwrap();
+        fs::write(&json_path, json).map_err(|e| Error::Io(json_path, e))
+    }
+
+    fn print_command(&self, command: &[String], env: &HashMap<String, String>) {
+        let mut env: Vec<_> = env.iter().collect();
+        env.sort();
+        let mut line =
+            format!("cd {} &&", shell_quote(&self.work_dir.to_string_lossy()));
+        if !env.is_empty() {
+            line.push_str(" env");
+        }
+        for (k, v) in env {
+            line.push(' ');
+            line.push_str(&shell_quote(&format!("{}={}", k, v)));
+        }
+        for arg in command {
+            line.push(' ');
+            line.push_str(&shell_quote(arg));
+        }
+        println!("{}", line);
+    }
+
+    /// Wraps `command` in a container invocation if the project is built in
+    /// a container.
+    pub fn command_line(
+        &self,
+        command: &[String],
+        env: &HashMap<String, String>,
+    ) -> Result<Vec<String>> {
+        self.command_line_with(command, env, true)
+    }
+
+    /// Like `command_line`, but only allocates a terminal in the container
+    /// if `tty` is set. Output that is captured must not get one, or its
+    /// lines end with `\r\n`.
+    fn command_line_with(
+        &self,
+        command: &[String],
+        env: &HashMap<String, String>,
+        tty: bool,
+    ) -> Result<Vec<String>> {
+        Ok(if self.container {
             let container_image = self
                 .container_image
                 .clone()
@@ -230,43 +848,183 @@ impl Project {
             iter::once(container_runtime.to_string_lossy().to_string())
                 .chain([
                     "run".to_string(),
-                    "-ti".to_string(),
+                    if tty { "-ti" } else { "-i" }.to_string(),
                     "--rm".to_string(),
                     "-v".to_string(),
                     format!("{0}:{0}", self.project_dir.display()),
                     "--workdir".to_string(),
                     self.work_dir.display().to_string(),
                 ])
-                .chain(self.env.iter().map(|(k, v)| format!("-e{}={}", k, v)))
+                .chain(env.iter().map(|(k, v)| format!("-e{}={}", k, v)))
                 .chain(self.container_args.clone().unwrap_or_default())
                 .chain(["--".to_string(), container_image])
                 .chain(command.iter().cloned())
                 .collect::<Vec<String>>()
         } else {
             command.to_vec()
+        })
+    }
+
+    /// The effective configuration of the project, as printed by
+    /// `mk -m config`.
+    pub fn describe(&self) -> Result<serde_json::Value> {
+        let configure_command = self.build_system.configure_command(self);
+        let configure_command = if configure_command.len() > 1 {
+            Some(self.command_line(&configure_command, &self.env)?)
+        } else {
+            None
         };
+        let build_command = self
+            .command_line(&self.build_system.build_command(self), &self.env)?;
 
-        Command::new(&command[0])
-            .args(command.iter().skip(1))
-            .envs(&self.env)
-            .current_dir(&self.work_dir)
-            .status()
-            .map_err(|e| Error::Command(command[0].clone(), e))
+        let mut description = serde_json::json!({
+            "project_dir": self.project_dir,
+            "root_reason": self.root_reason,
+            "work_dir": self.work_dir,
+            "build_system": self.build_system.name(),
+            "mk_info_path": self.mk_info_path,
+            "mk_info_sources": self.mk_info.sources,
+            "modes": self.modes,
+            "build_info": self.build_info,
+            "build_dir": self.build_dir,
+            "diagnostics_dir": self.diagnostics_dir(),
+            "env": self.env,
+            "container": {
+                "enabled": self.container,
+                "image": self.container_image,
+                "args": self.container_args,
+                "runtime": self.container_runtime,
+            },
+            "configure_command": configure_command,
+            "build_command": build_command,
+        });
+        remove_nulls(&mut description);
+        Ok(description)
+    }
+    fn run_hooks(
+        &self,
+        hooks: &[Vec<String>],
+        extra_env: &HashMap<String, String>,
+    ) -> Result<ExitStatus> {
+        for hook in hooks.iter().filter(|x| !x.is_empty()) {
+            let status = self.run_with_env(hook, extra_env)?;
+            if !status.success() {
+                return Ok(status);
+            }
+        }
+        Ok(ExitStatus::default())
     }
+
     pub fn build(&self) -> Result<ExitStatus> {
+        let status = self.run_hooks(&self.pre_build, &HashMap::new())?;
+        if !status.success() {
+            return Ok(status);
+        }
+
         let cmd = self.build_system.build_command(self);
-        self.run(&cmd)
+        let status = self.run_step(&cmd)?;
+
+        let build_status = status.code().unwrap_or(-1).to_string();
+        let post_env =
+            HashMap::from([("MK_BUILD_STATUS".into(), build_status)]);
+        let post_status = self.run_hooks(&self.post_build, &post_env)?;
+        if status.success() {
+            Ok(post_status)
+        } else {
+            Ok(status)
+        }
     }
 
     pub fn configure(&self) -> Result<ExitStatus> {
+        let status = self.run_hooks(&self.pre_configure, &HashMap::new())?;
+        if !status.success() {
+            return Ok(status);
+        }
+
         let cmd = self.build_system.configure_command(self);
         if cmd.len() > 1 {
-            self.run(&cmd)
+            self.run_step(&cmd)
         } else {
             Ok(ExitStatus::default())
         }
     }
 
+    /// Writes the compilation database with `command` if the configure
+    /// step did not.
+    fn generate_compile_commands(
+        &self,
+        command: &[String],
+        database: &Path,
+    ) -> Result<()> {
+        let command = self.command_line_with(command, &self.env, false)?;
+        let output = Command::new(&command[0])
+            .args(command.iter().skip(1))
+            .envs(&self.env)
+            .current_dir(&self.work_dir)
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| Error::Command(command[0].clone(), e))?;
+        if output.status.success() {
+            fs::write(database, output.stdout)
+                .map_err(|e| Error::Io(database.into(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Links the compilation database of the build directory into the
+    /// project root, where editors look for it. Existing files that are not
+    /// symlinks are left alone.
+    pub fn link_compile_commands(&self) -> Result<()> {
+        let Some(database) = self.build_system.compile_commands(self) else {
+            return Ok(());
+        };
+        if !self.dry_run && !database.exists() {
+            if let Some(cmd) = self.build_system.compile_commands_command(self)
+            {
+                self.generate_compile_commands(&cmd, &database)?;
+            }
+            if !database.exists() {
+                if !self.build_system.exports_compile_commands(self) {
+                    eprintln!(
+                        "Warning: {} does not write compile_commands.json, \
+                         reconfigure with -mr to enable it",
+                        self.build_dir.display()
+                    );
+                }
+                return Ok(());
+            }
+        }
+
+        let link = self.project_dir.join("compile_commands.json");
+        let target = database
+            .strip_prefix(&self.project_dir)
+            .unwrap_or(&database);
+        match fs::symlink_metadata(&link) {
+            Ok(metadata) if !metadata.file_type().is_symlink() => return Ok(()),
+            Ok(_) if fs::read_link(&link).is_ok_and(|x| x == target) => {
+                return Ok(())
+            }
+            _ => {}
+        }
+
+        if self.dry_run {
+            println!(
+                "ln -sfn {} {}",
+                shell_quote(&target.to_string_lossy()),
+                shell_quote(&link.to_string_lossy())
+            );
+            return Ok(());
+        }
+        match fs::remove_file(&link) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(Error::Io(link, e))
+            }
+            _ => {}
+        }
+        std::os::unix::fs::symlink(target, &link)
+            .map_err(|e| Error::Io(link, e))
+    }
+
     fn get_mtime(path: &Path) -> Result<SystemTime> {
         path.metadata()
             .and_then(|x| x.modified())
@@ -287,12 +1045,119 @@ impl Project {
 
         let marker_time = Self::get_mtime(&marker)?;
 
-        let mk_info_time = if let Some(mk_info_path) = &self.mk_info_path {
-            Self::get_mtime(mk_info_path)?
-        } else {
-            return Ok(true);
+        for source in &self.mk_info.sources {
+            if Self::get_mtime(source)? >= marker_time {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails to configure like meson with an unknown variable.
+    #[derive(Debug)]
+    struct FailingConfigure;
+
+    impl BuildSystem for FailingConfigure {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn is_project_root(
+            &self,
+            _path: &Path,
+        ) -> Result<RootIdentificationResult> {
+            Ok(RootIdentificationResult::NotRoot)
+        }
+
+        fn configure_marker(
+            &self,
+            project: &Project,
+        ) -> Result<Option<PathBuf>> {
+            Ok(Some(project.build_dir.join("build.ninja")))
+        }
+
+        fn configure_command(&self, project: &Project) -> Vec<String> {
+            let script = format!(
+                "mkdir -p {}; echo 'meson.build:3:0: ERROR: Unknown variable \
+                 \"foo\".' >&2; exit 1",
+                project.build_dir.display()
+            );
+            vec!["sh".into(), "-c".into(), script]
+        }
+
+        fn build_command(&self, _project: &Project) -> Vec<String> {
+            vec!["true".into()]
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "mk-project-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    /// A Make project in `root`/app with its build directory next to it.
+    fn project(root: &Path) -> Project {
+        let dir = root.join("app");
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join("Makefile"), "").unwrap();
+        let opts = Opts {
+            cwd: dir,
+            build_dir: Some(root.join("build")),
+            ..Default::default()
         };
+        Project::from_opts(&opts).unwrap()
+    }
+
+    #[test]
+    fn configure_failure_is_written_to_quickfix() {
+        let root = temp_dir("configure");
+        let mut project = project(&root);
+        fs::write(root.join("app/meson.build"), "").unwrap();
+        project.build_system = &FailingConfigure;
+
+        project.clear_diagnostics();
+        assert!(!project.configure().unwrap().success());
+        project.write_diagnostics().unwrap();
+        let quickfix = root.join("build").join(QUICKFIX_FILE);
+        assert_eq!(
+            fs::read_to_string(&quickfix).unwrap(),
+            "meson.build:3:0: error: Unknown variable \"foo\".\n"
+        );
+        // The build directory outside the project only holds the
+        // diagnostics, so it can be cleaned for the next attempt.
+        project.clean().unwrap();
+        assert!(!root.join("build").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn background_processes_do_not_block() {
+        let root = temp_dir("background");
+        let mut project = project(&root);
+        project.output_prefix = Some("app".into());
+
+        // The background process inherits the output pipes.
+        let command = ["sh", "-c", "echo done; sleep 30 & exit 3"];
+        let mut cmd = Command::new(command[0]);
+        cmd.args(&command[1..]);
+        let start = Instant::now();
+        let status = project.run_captured(cmd, true).unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert!(start.elapsed() < Duration::from_secs(10));
 
-        Ok(marker_time > mk_info_time)
+        fs::remove_dir_all(&root).unwrap();
     }
 }
@@ -0,0 +1,249 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use jobserver::Client;
+
+use crate::{
+    dependencies::Graph, project::Project, summary::Summary, Error, Result,
+};
+
+fn failed(result: &Result<Summary>) -> bool {
+    !result.as_ref().is_ok_and(|x| x.success())
+}
+
+/// Runs independent projects of a dependency graph concurrently. With more
+/// than one job, mk acts as a GNU make jobserver: every running project
+/// holds a job slot, and nested make, ninja or cargo processes take further
+/// slots from the same pool.
+pub struct Scheduler {
+    jobs: usize,
+    jobserver: Option<Client>,
+    /// The slot mk itself holds, like the implicit slot of make.
+    implicit_slot: AtomicBool,
+}
+
+impl Scheduler {
+    pub fn new(jobs: usize) -> Result<Self> {
+        let jobserver = if jobs > 1 {
+            Some(Client::new(jobs - 1).map_err(Error::Jobserver)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            jobs: jobs.max(1),
+            jobserver,
+            implicit_slot: AtomicBool::new(true),
+        })
+    }
+
+    /// Passes the jobserver to the projects and prefixes their output with
+    /// `label` if they are built concurrently.
+    pub fn prepare(&self, graph: &mut Graph, label: impl Fn(&Path) -> String) {
+        if self.jobserver.is_none() {
+            return;
+        }
+        for project in &mut graph.projects {
+            project.jobserver = self.jobserver.clone();
+            project.output_prefix = Some(label(&project.project_dir));
+        }
+    }
+
+    /// Builds the projects of `graph`. A project is started once all of its
+    /// dependencies succeeded and skipped if one of them failed. Returns the
+    /// result of each project.
+    pub fn run<F>(&self, graph: &Graph, build: F) -> Vec<Result<Summary>>
+    where
+        F: Fn(&Project) -> Result<Summary> + Sync,
+    {
+        let count = graph.projects.len();
+        let mut results: Vec<Option<Result<Summary>>> =
+            (0..count).map(|_| None).collect();
+        let mut started = vec![false; count];
+        let mut running = 0;
+        let (tx, rx) = mpsc::channel();
+
+        thread::scope(|scope| loop {
+            // Dependencies come first in the graph, so a single pass sees
+            // the results of all finished or skipped dependencies.
+            for i in 0..count {
+                if started[i] || running >= self.jobs {
+                    continue;
+                }
+                let dependencies = &graph.dependencies[i];
+                let failed = dependencies
+                    .iter()
+                    .find(|x| results[**x].as_ref().is_some_and(failed));
+                if let Some(dependency) = failed {
+                    let dir = &graph.projects[*dependency].project_dir;
+                    results[i] =
+                        Some(Err(Error::DependencyFailed(dir.clone())));
+                    started[i] = true;
+                } else if dependencies.iter().all(|x| results[*x].is_some()) {
+                    let tx = tx.clone();
+                    let build = &build;
+                    let project = &graph.projects[i];
+                    scope.spawn(move || {
+                        // Report a panic as well, or the loop below would
+                        // wait for this project forever.
+                        let result =
+                            panic::catch_unwind(AssertUnwindSafe(|| {
+                                self.with_slot(|| build(project))
+                            }));
+                        let _ = tx.send((i, result));
+                    });
+                    started[i] = true;
+                    running += 1;
+                }
+            }
+            if running == 0 {
+                break;
+            }
+            // `tx` is still alive, so the channel cannot be disconnected.
+            let (i, result) = rx.recv().unwrap();
+            let result = result.unwrap_or_else(|e| panic::resume_unwind(e));
+            results[i] = Some(result);
+            running -= 1;
+        });
+
+        results.into_iter().flatten().collect()
+    }
+
+    /// Runs `f` while holding a job slot.
+    fn with_slot<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let Some(jobserver) = &self.jobserver else {
+            return f();
+        };
+        if self.implicit_slot.swap(false, Ordering::SeqCst) {
+            let result = f();
+            self.implicit_slot.store(true, Ordering::SeqCst);
+            result
+        } else {
+            let _token = jobserver.acquire().map_err(Error::Jobserver)?;
+            f()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::PathBuf,
+        sync::{atomic::AtomicUsize, Mutex},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::Opts;
+
+    /// A graph of Make projects in a temporary directory. `dependencies`
+    /// holds the indices of the dependencies of each project.
+    fn graph(name: &str, dependencies: Vec<Vec<usize>>) -> (PathBuf, Graph) {
+        let root = std::env::temp_dir().join(format!(
+            "mk-scheduler-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        let projects = (0..dependencies.len())
+            .map(|i| {
+                let dir = root.join(i.to_string());
+                fs::create_dir_all(dir.join(".git")).unwrap();
+                fs::write(dir.join("Makefile"), "").unwrap();
+                let opts = Opts {
+                    cwd: dir,
+                    ..Default::default()
+                };
+                Project::from_opts(&opts).unwrap()
+            })
+            .collect();
+        let graph = Graph {
+            projects,
+            dependencies,
+        };
+        (root, graph)
+    }
+
+    fn index(project: &Project) -> usize {
+        let name = project.project_dir.file_name().unwrap();
+        name.to_str().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn runs_dependencies_first_and_others_concurrently() {
+        let (root, graph) = graph("order", vec![vec![], vec![0], vec![]]);
+        let events = Mutex::new(vec![]);
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+
+        let scheduler = Scheduler::new(2).unwrap();
+        let results = scheduler.run(&graph, |project| {
+            let i = index(project);
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(now, Ordering::SeqCst);
+            events.lock().unwrap().push(("start", i));
+            thread::sleep(Duration::from_millis(50));
+            events.lock().unwrap().push(("end", i));
+            running.fetch_sub(1, Ordering::SeqCst);
+            Ok(Summary::new(project))
+        });
+
+        assert!(results.iter().all(|x| x.is_ok()));
+        let events = events.into_inner().unwrap();
+        let position = |event| events.iter().position(|x| *x == event);
+        assert!(position(("end", 0)) < position(("start", 1)));
+        assert_eq!(max_running.into_inner(), 2);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn skips_dependents_of_failed_projects() {
+        let deps = vec![vec![], vec![0], vec![1], vec![]];
+        let (root, graph) = graph("failed", deps);
+
+        let scheduler = Scheduler::new(1).unwrap();
+        let results = scheduler.run(&graph, |project| match index(project) {
+            0 => Err(Error::MissingContainerImage),
+            _ => Ok(Summary::new(project)),
+        });
+
+        let dir = &graph.projects[0].project_dir;
+        assert!(
+            matches!(&results[1], Err(Error::DependencyFailed(x)) if x == dir)
+        );
+        let dir = &graph.projects[1].project_dir;
+        assert!(
+            matches!(&results[2], Err(Error::DependencyFailed(x)) if x == dir)
+        );
+        assert!(results[3].is_ok());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn propagates_panics() {
+        let (root, graph) = graph("panic", vec![vec![], vec![0], vec![]]);
+
+        let scheduler = Scheduler::new(2).unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            scheduler.run(&graph, |project| {
+                if index(project) == 0 {
+                    panic!("build panicked");
+                }
+                Ok(Summary::new(project))
+            })
+        }));
+        let payload = result.err().unwrap();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"build panicked"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+use std::{fmt, process::ExitStatus, time::Duration};
+
+use crate::{diagnostics::Severity, project::Project};
+
+/// The outcome of building a project, printed after the build.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub build_system: &'static str,
+    pub modes: Vec<String>,
+    /// Not set if the project was already configured.
+    pub configure_time: Option<Duration>,
+    /// Not set if the build did not run.
+    pub build_time: Option<Duration>,
+    pub status: ExitStatus,
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64();
+    if secs < 60.0 {
+        format!("{:.1}s", secs)
+    } else {
+        format!(
+            "{}m{:02}s",
+            duration.as_secs() / 60,
+            duration.as_secs() % 60
+        )
+    }
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, word)
+    } else {
+        format!("{} {}s", count, word)
+    }
+}
+
+impl Summary {
+    pub fn new(project: &Project) -> Self {
+        Self {
+            build_system: project.build_system.name(),
+            modes: project.modes.clone(),
+            configure_time: None,
+            build_time: None,
+            status: ExitStatus::default(),
+            errors: 0,
+            warnings: 0,
+        }
+    }
+
+    pub fn count_diagnostics(&mut self, project: &Project) {
+        let diagnostics = project.diagnostics.lock().unwrap();
+        let count = |severity| {
+            diagnostics
+                .iter()
+                .filter(|x| x.severity == severity)
+                .count()
+        };
+        self.errors = count(Severity::Error);
+        self.warnings = count(Severity::Warning);
+    }
+
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+
+    pub fn glyph(&self) -> &'static str {
+        if self.success() {
+            "✅"
+        } else {
+            "❌"
+        }
+    }
+
+    /// The summary without the glyph, e.g. `meson (debug), configure 1.2s,
+    /// build 4.0s, exit status: 0, 0 errors, 2 warnings`.
+    pub fn details(&self) -> String {
+        let mut parts =
+            vec![format!("{} ({})", self.build_system, self.modes.join(" "))];
+        if let Some(time) = self.configure_time {
+            parts.push(format!("configure {}", format_duration(time)));
+        }
+        if let Some(time) = self.build_time {
+            parts.push(format!("build {}", format_duration(time)));
+        }
+        parts.push(self.status.to_string());
+        parts.push(plural(self.errors, "error"));
+        parts.push(plural(self.warnings, "warning"));
+        parts.join(", ")
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.glyph(), self.details())
+    }
+}
@@ -0,0 +1,429 @@
+use std::fmt;
+
+use serde::{
+    de::{
+        self, value, DeserializeSeed, Deserializer, IgnoredAny, MapAccess,
+        SeqAccess, Visitor,
+    },
+    forward_to_deserialize_any, Deserialize,
+};
+
+use crate::{
+    condition::Condition,
+    mk_info::{BuildInfo, MKINFO_FIELDS},
+};
+
+static CONTAINER_FIELDS: &[&str] = &["image", "opts"];
+
+/// Fields of `BuildInfo` that have no meaning at the top level of a file.
+static NESTED_FIELDS: &[&str] = &["extends", "when"];
+
+/// Captures the field names serde derives for a struct.
+struct FieldNames(&'static [&'static str]);
+
+impl<'de> Deserializer<'de> for &mut FieldNames {
+    type Error = value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0 = fields;
+        Err(de::Error::custom("not deserializing"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+fn fields_of<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut names = FieldNames(&[]);
+    let _ = T::deserialize(&mut names);
+    names.0
+}
+
+/// Returns the candidate closest to `name`, if it is close enough to be a
+/// plausible typo.
+pub fn suggest<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let max_distance = (name.len() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|x| (distance(name, x), x))
+        .filter(|(d, _)| *d <= max_distance)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, x)| x)
+}
+
+/// Levenshtein distance, treating `-` and `_` as equal.
+fn distance(a: &str, b: &str) -> usize {
+    let norm = |c| if c == '-' { '_' } else { c };
+    let b: Vec<char> = b.chars().map(norm).collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().map(norm).enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { prev } else { prev + 1 };
+            prev = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(prev + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The parts of the Mk file format whose keys are checked.
+#[derive(Clone, Copy)]
+enum Shape {
+    Any,
+    MkInfo,
+    BuildInfo,
+    BuildInfoList,
+    Modes,
+    Merge,
+    Condition,
+    Container,
+}
+
+impl Shape {
+    fn fields(self) -> Option<Vec<&'static str>> {
+        match self {
+            Shape::MkInfo => {
+                let mut fields = fields_of::<BuildInfo>().to_vec();
+                fields.retain(|x| !NESTED_FIELDS.contains(x));
+                fields.extend(MKINFO_FIELDS);
+                Some(fields)
+            }
+            Shape::BuildInfo | Shape::Merge => {
+                Some(fields_of::<BuildInfo>().to_vec())
+            }
+            Shape::Condition => Some(fields_of::<Condition>().to_vec()),
+            Shape::Container => Some(CONTAINER_FIELDS.to_vec()),
+            _ => None,
+        }
+    }
+
+    fn value(self, key: &str) -> Shape {
+        match (self, key) {
+            (Shape::Modes, _) => Shape::BuildInfo,
+            (Shape::MkInfo, "mode") => Shape::Modes,
+            (Shape::MkInfo | Shape::BuildInfo, "conditional") => {
+                Shape::BuildInfoList
+            }
+            (Shape::MkInfo | Shape::BuildInfo, "merge") => Shape::Merge,
+            (Shape::MkInfo | Shape::BuildInfo, "when") => Shape::Condition,
+            (Shape::MkInfo | Shape::BuildInfo, "container") => Shape::Container,
+            _ => Shape::Any,
+        }
+    }
+
+    fn element(self) -> Shape {
+        match self {
+            Shape::BuildInfoList => Shape::BuildInfo,
+            _ => Shape::Any,
+        }
+    }
+}
+
+struct Key(Shape);
+
+impl<'de> DeserializeSeed<'de> for Key {
+    type Value = Shape;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Shape, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Key {
+    type Value = Shape;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a key")
+    }
+
+    fn visit_str<E: de::Error>(self, key: &str) -> Result<Shape, E> {
+        let Some(fields) = self.0.fields() else {
+            return Ok(self.0.value(key));
+        };
+        if fields.contains(&key) {
+            return Ok(self.0.value(key));
+        }
+        if matches!(self.0, Shape::MkInfo) && NESTED_FIELDS.contains(&key) {
+            return Err(E::custom(format!(
+                "`{}` is only valid in modes and conditional blocks",
+                key
+            )));
+        }
+        Err(match suggest(key, fields.iter().copied()) {
+            Some(x) => E::custom(format!(
+                "unknown field `{}`, did you mean `{}`?",
+                key, x
+            )),
+            None => E::custom(format!("unknown field `{}`", key)),
+        })
+    }
+}
+
+struct Validate(Shape);
+
+impl<'de> DeserializeSeed<'de> for Validate {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<(), D::Error> {
+        match self.0 {
+            Shape::Any => {
+                deserializer.deserialize_ignored_any(IgnoredAny).map(|_| ())
+            }
+            _ => deserializer.deserialize_any(self),
+        }
+    }
+}
+
+impl<'de> Visitor<'de> for Validate {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(shape) = map.next_key_seed(Key(self.0))? {
+            map.next_value_seed(Validate(shape))?;
+        }
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while seq.next_element_seed(Validate(self.0.element()))?.is_some() {}
+        Ok(())
+    }
+
+    fn visit_bool<E: de::Error>(self, _v: bool) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_i64<E: de::Error>(self, _v: i64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_u64<E: de::Error>(self, _v: u64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_f64<E: de::Error>(self, _v: f64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_str<E: de::Error>(self, _v: &str) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_some<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<(), D::Error> {
+        DeserializeSeed::deserialize(self, deserializer)
+    }
+}
+
+/// Rejects unknown keys in a Mk file. The error is raised while the key is
+/// deserialized, so the format reports the position of the offending key.
+pub struct Validator;
+
+impl<'de> DeserializeSeed<'de> for Validator {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<(), D::Error> {
+        Validate(Shape::MkInfo).deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(yaml: &str) -> Result<(), String> {
+        Validator
+            .deserialize(serde_yaml::Deserializer::from_str(yaml))
+            .map_err(|e| e.to_string())
+    }
+
+    fn assert_rejects(yaml: &str, message: &str) {
+        let error = validate(yaml).unwrap_err();
+        assert!(error.contains(message), "{:?} in {:?}", message, error);
+    }
+
+    #[test]
+    fn accepts_full_config() {
+        let yaml = r#"
+build_system: cmake
+default: [test]
+configure: [-DCMAKE_BUILD_TYPE=Debug]
+build_dir: build
+container:
+  image: fedora
+  opts: [--privileged]
+container_runtime: podman
+env:
+  ANY_NAME: value
+pre_configure: [echo]
+pre_build: [[echo, hi]]
+post_build: ["true"]
+depends_on: [../lib]
+include: [common.yaml]
+root: true
+default_mode: debug
+conditional:
+  - when:
+      os: linux
+      arch: [x86_64, aarch64]
+      hostname: box
+      tool: ninja
+      env: CI
+    configure: [-G, Ninja]
+mode:
+  debug:
+    description: Debug build
+    extends: base
+    merge:
+      configure: replace
+    when:
+      tool: gcc
+    conditional:
+      - when: {os: linux}
+        env: {A: b}
+"#;
+        assert_eq!(validate(yaml), Ok(()));
+    }
+
+    #[test]
+    fn rejects_unknown_top_level_key() {
+        assert_rejects("build_sytem: make\n", "unknown field `build_sytem`");
+    }
+
+    #[test]
+    fn rejects_unknown_key_in_mode() {
+        assert_rejects(
+            "mode:\n  debug:\n    configur: []\n",
+            "unknown field `configur`, did you mean `configure`?",
+        );
+    }
+
+    #[test]
+    fn rejects_top_level_only_keys_in_mode() {
+        assert_rejects("mode:\n  debug:\n    include: []\n", "`include`");
+    }
+
+    #[test]
+    fn rejects_unknown_key_in_conditional_block() {
+        assert_rejects("conditional:\n  - defualt: []\n", "`defualt`");
+    }
+
+    #[test]
+    fn mkinfo_fields_match_schema() {
+        let schema = schemars::schema_for!(crate::mk_info::MkInfo);
+        let properties = &schema.schema.object.unwrap().properties;
+        let mut expected: Vec<_> =
+            properties.keys().map(|x| x.as_str()).collect();
+        let mut fields = fields_of::<BuildInfo>().to_vec();
+        fields.extend(MKINFO_FIELDS);
+        expected.sort();
+        fields.sort();
+        assert_eq!(fields, expected);
+    }
+
+    #[test]
+    fn rejects_top_level_mode_keys() {
+        assert_rejects("extends: base\n", "`extends` is only valid in modes");
+        assert_rejects("when: {os: linux}\n", "`when` is only valid in modes");
+    }
+
+    #[test]
+    fn rejects_unknown_key_in_when() {
+        assert_rejects(
+            "mode:\n  a:\n    when: {tools: ninja}\n",
+            "unknown field `tools`, did you mean `tool`?",
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_key_in_container() {
+        assert_rejects(
+            "container: {image: x, opt: []}\n",
+            "unknown field `opt`, did you mean `opts`?",
+        );
+    }
+
+    #[test]
+    fn checks_merge_keys_but_not_strategies() {
+        assert_eq!(
+            validate("mode:\n  a:\n    merge: {env: anything}\n"),
+            Ok(())
+        );
+        assert_rejects("mode:\n  a:\n    merge: {envs: replace}\n", "`envs`");
+    }
+
+    #[test]
+    fn ignores_keys_of_free_form_maps() {
+        assert_eq!(
+            validate("env: {configur: x}\nmode: {any_name: {}}\n"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn reports_position_of_key() {
+        assert_rejects("default: []\nbuild_sytem: make\n", "line 2 column 1");
+    }
+
+    #[test]
+    fn suggests_within_a_third_of_the_length() {
+        let fields = ["build_dir", "abcdef"];
+        assert_eq!(suggest("build-dir", fields), Some("build_dir"));
+        assert_eq!(suggest("bild_dr", fields), Some("build_dir"));
+        assert_eq!(suggest("abcdXY", fields), Some("abcdef"));
+        assert_eq!(suggest("abcXYZ", fields), None);
+        assert_eq!(suggest("x", ["y"]), Some("y"));
+        assert_eq!(suggest("xy", ["zz"]), None);
+    }
+
+    #[test]
+    fn field_names_are_captured() {
+        let fields = fields_of::<BuildInfo>();
+        assert!(fields.contains(&"configure"));
+        assert!(fields.contains(&"depends_on"));
+        assert!(fields_of::<Condition>().contains(&"hostname"));
+    }
+}
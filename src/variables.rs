@@ -0,0 +1,149 @@
+use std::{collections::HashMap, env, path::Path, thread};
+
+use crate::{Error, Result};
+
+/// Variables that can be referenced as `${name}` in Mk files. Built-in
+/// variables take precedence over the environment.
+pub struct Variables {
+    builtins: HashMap<&'static str, String>,
+}
+
+impl Variables {
+    pub fn new(project_dir: &Path, modes: &[String]) -> Self {
+        let nproc = thread::available_parallelism().map_or(1, |x| x.get());
+        let builtins = HashMap::from([
+            ("project_dir", project_dir.display().to_string()),
+            ("mode", modes.join(" ")),
+            ("host_arch", env::consts::ARCH.to_string()),
+            ("host_os", env::consts::OS.to_string()),
+            ("nproc", nproc.to_string()),
+        ]);
+        Self { builtins }
+    }
+
+    /// Sets the `build_dir` variable. It is not available while the build
+    /// directory itself is expanded.
+    pub fn set_build_dir(&mut self, build_dir: &Path) {
+        self.builtins
+            .insert("build_dir", build_dir.display().to_string());
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        self.builtins
+            .get(name)
+            .cloned()
+            .or_else(|| env::var(name).ok())
+    }
+
+    /// Expands `${name}` references in `value`. `$$` is a literal `$`.
+    pub fn expand(&self, value: &str) -> Result<String> {
+        self.expand_with(value, false)
+    }
+
+    /// Expands a hook, which is usually a shell script. References to
+    /// unknown variables are left to the shell, as is `$$` unless it
+    /// escapes a reference like `$${mode}`.
+    pub fn expand_script(&self, value: &str) -> Result<String> {
+        self.expand_with(value, true)
+    }
+
+    fn expand_with(&self, value: &str, script: bool) -> Result<String> {
+        let mut result = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(pos) = rest.find('$') {
+            result.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            if let Some(tail) = rest.strip_prefix('$') {
+                if script && !tail.starts_with('{') {
+                    result.push('$');
+                }
+                result.push('$');
+                rest = tail;
+            } else if let Some(tail) = rest.strip_prefix('{') {
+                let end = tail
+                    .find('}')
+                    .ok_or_else(|| Error::UnterminatedVariable(value.into()))?;
+                let name = &tail[..end];
+                match self.lookup(name) {
+                    Some(expanded) => result.push_str(&expanded),
+                    None if script => {
+                        result.push_str(&format!("${{{}}}", name));
+                    }
+                    None => return Err(Error::UnknownVariable(name.into())),
+                }
+                rest = &tail[end + 1..];
+            } else {
+                result.push('$');
+            }
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+
+    pub fn expand_all(&self, values: Vec<String>) -> Result<Vec<String>> {
+        values.iter().map(|x| self.expand(x)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Variables {
+        Variables::new(Path::new("/src/app"), &["debug".into()])
+    }
+
+    #[test]
+    fn expands_builtins() {
+        let value = vars().expand("${project_dir}/out-${mode}").unwrap();
+        assert_eq!(value, "/src/app/out-debug");
+    }
+
+    #[test]
+    fn double_dollar_is_literal() {
+        assert_eq!(vars().expand("$${mode}").unwrap(), "${mode}");
+        assert_eq!(vars().expand("a$$b").unwrap(), "a$b");
+    }
+
+    #[test]
+    fn dollar_before_text_is_kept() {
+        assert_eq!(vars().expand("$mode $ x$").unwrap(), "$mode $ x$");
+    }
+
+    #[test]
+    fn unterminated_reference_is_an_error() {
+        let error = vars().expand("-D${mode").unwrap_err();
+        assert!(
+            matches!(error, Error::UnterminatedVariable(x) if x == "-D${mode")
+        );
+        let error = vars().expand_script("echo ${mode").unwrap_err();
+        assert!(matches!(error, Error::UnterminatedVariable(_)));
+    }
+
+    #[test]
+    fn unknown_name_is_an_error() {
+        let error = vars().expand("${mk_test_unknown}").unwrap_err();
+        assert!(
+            matches!(error, Error::UnknownVariable(x) if x == "mk_test_unknown")
+        );
+    }
+
+    #[test]
+    fn scripts_keep_unknown_names() {
+        let script = "for f in a b; do echo ${f} ${mode}; done";
+        assert_eq!(
+            vars().expand_script(script).unwrap(),
+            "for f in a b; do echo ${f} debug; done"
+        );
+    }
+
+    #[test]
+    fn scripts_keep_double_dollar() {
+        assert_eq!(vars().expand_script("echo $$").unwrap(), "echo $$");
+        assert_eq!(vars().expand_script("kill $$ $!").unwrap(), "kill $$ $!");
+        assert_eq!(
+            vars().expand_script("echo $${mode}").unwrap(),
+            "echo ${mode}"
+        );
+    }
+}